@@ -0,0 +1,226 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+use anyhow::{Result, anyhow};
+
+const SAMPLE_INTERVAL: Duration = Duration::from_secs(1);
+// weight of a new sample in the running average, evens out shadows and flicker
+const SMOOTHING: f64 = 0.3;
+// relative change in light needed before the brightness follows, so it
+// does not wander back and forth around a point of the curve
+const HYSTERESIS: f64 = 0.15;
+
+fn read_value(path: &Path) -> Result<f64> {
+    Ok(fs::read_to_string(path)?.trim().parse::<f64>()?)
+}
+
+// The first IIO device with an illuminance channel, below root for testing
+fn find_sensor(root: &Path) -> Result<PathBuf> {
+    let mut devices = fs::read_dir(root.join("bus/iio/devices"))?
+        .filter_map(|e| e.ok())
+        .map(|e| e.path())
+        .filter(|path| path.join("in_illuminance_raw").exists() || path.join("in_illuminance_input").exists())
+        .collect::<Vec<_>>();
+    devices.sort();
+    devices.into_iter().next().ok_or(anyhow!("No ambient light sensor found"))
+}
+
+// Brightness for the light level, interpolated between the points of the
+// curve which are sorted by lux
+fn interpolate(curve: &[(f64, u32)], lux: f64) -> u32 {
+    let Some(first) = curve.first() else {
+        return 0;
+    };
+    if lux <= first.0 {
+        return first.1;
+    }
+    for pair in curve.windows(2) {
+        let ((x0, y0), (x1, y1)) = (pair[0], pair[1]);
+        if lux <= x1 {
+            let t = (lux - x0) / (x1 - x0).max(f64::EPSILON);
+            return (y0 as f64 + t * (y1 as f64 - y0 as f64)).round() as u32;
+        }
+    }
+    curve.last().unwrap().1
+}
+
+// Maps the light reported by the ambient light sensor to a brightness
+pub struct AmbientLight {
+    device: PathBuf,
+    curve: Vec<(f64, u32)>,
+    smoothed: Option<f64>,
+    // light level the current brightness was picked for
+    applied: Option<f64>,
+    next_sample: Instant,
+    // avoid repeating the same error every sample
+    warned: bool,
+}
+
+impl AmbientLight {
+    pub fn new(sysfs_root: &str, curve: &[(f64, u32)]) -> Result<AmbientLight> {
+        if curve.is_empty() {
+            return Err(anyhow!("The brightness curve is empty"));
+        }
+        let mut curve = curve.to_vec();
+        curve.sort_by(|a, b| a.0.total_cmp(&b.0));
+        Ok(AmbientLight {
+            device: find_sensor(Path::new(sysfs_root))?,
+            curve,
+            smoothed: None,
+            applied: None,
+            next_sample: Instant::now(),
+            warned: false,
+        })
+    }
+    // Light level in lux, drivers either report it directly or as a raw
+    // value with a scale and offset
+    fn read_lux(&self) -> Result<f64> {
+        if let Ok(lux) = read_value(&self.device.join("in_illuminance_input")) {
+            return Ok(lux);
+        }
+        let raw = read_value(&self.device.join("in_illuminance_raw"))?;
+        let scale = read_value(&self.device.join("in_illuminance_scale")).unwrap_or(1.0);
+        let offset = read_value(&self.device.join("in_illuminance_offset")).unwrap_or(0.0);
+        Ok((raw + offset) * scale)
+    }
+    // Samples the sensor when due, returns a brightness if it should change
+    pub fn update(&mut self) -> Option<u32> {
+        if Instant::now() < self.next_sample {
+            return None;
+        }
+        self.next_sample = Instant::now() + SAMPLE_INTERVAL;
+        let lux = match self.read_lux() {
+            Ok(lux) => lux.max(0.0),
+            Err(e) => {
+                if !self.warned {
+                    eprintln!("Failed to read ambient light sensor: {}", e);
+                    self.warned = true;
+                }
+                return None;
+            }
+        };
+        self.warned = false;
+        let smoothed = match self.smoothed {
+            Some(smoothed) => smoothed + (lux - smoothed) * SMOOTHING,
+            None => lux,
+        };
+        self.smoothed = Some(smoothed);
+        if let Some(applied) = self.applied {
+            // at least a lux of change keeps dark rooms from hunting over noise
+            if (smoothed - applied).abs() <= (applied * HYSTERESIS).max(1.0) {
+                return None;
+            }
+        }
+        self.applied = Some(smoothed);
+        Some(interpolate(&self.curve, smoothed))
+    }
+    pub fn next_update(&self) -> Instant {
+        self.next_sample
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{env, process};
+
+    const CURVE: [(f64, u32); 3] = [(0.0, 10), (100.0, 110), (1000.0, 200)];
+
+    // A sysfs tree of IIO devices in the temp dir, removed when dropped
+    struct FakeSysfs(PathBuf);
+
+    impl FakeSysfs {
+        fn new(test: &str) -> FakeSysfs {
+            let root = env::temp_dir().join(format!("tiny-dfr-als-{}-{}", process::id(), test));
+            let _ = fs::remove_dir_all(&root);
+            fs::create_dir_all(root.join("bus/iio/devices")).unwrap();
+            FakeSysfs(root)
+        }
+        fn device(&self, name: &str, attributes: &[(&str, &str)]) -> PathBuf {
+            let device = self.0.join("bus/iio/devices").join(name);
+            fs::create_dir_all(&device).unwrap();
+            for (attribute, value) in attributes {
+                fs::write(device.join(attribute), value).unwrap();
+            }
+            device
+        }
+        fn root(&self) -> &str {
+            self.0.to_str().unwrap()
+        }
+    }
+
+    impl Drop for FakeSysfs {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    // Takes a sample right away instead of waiting for the interval
+    fn sample(light: &mut AmbientLight, device: &Path, lux: &str) -> Option<u32> {
+        fs::write(device.join("in_illuminance_input"), lux).unwrap();
+        light.next_sample = Instant::now();
+        light.update()
+    }
+
+    #[test]
+    fn finds_the_first_light_sensor() {
+        let sysfs = FakeSysfs::new("find");
+        sysfs.device("iio:device0", &[("in_accel_x_raw", "0")]);
+        let sensor = sysfs.device("iio:device1", &[("in_illuminance_raw", "5")]);
+        sysfs.device("iio:device2", &[("in_illuminance_input", "5")]);
+        assert_eq!(find_sensor(&sysfs.0).unwrap(), sensor);
+    }
+
+    #[test]
+    fn missing_sensor_fails() {
+        let sysfs = FakeSysfs::new("missing");
+        sysfs.device("iio:device0", &[("in_accel_x_raw", "0")]);
+        assert!(AmbientLight::new(sysfs.root(), &CURVE).is_err());
+        assert!(AmbientLight::new("/nonexistent", &CURVE).is_err());
+    }
+
+    #[test]
+    fn reads_lux() {
+        let sysfs = FakeSysfs::new("lux");
+        let device = sysfs.device("iio:device0", &[("in_illuminance_input", "42.5\n")]);
+        let light = AmbientLight::new(sysfs.root(), &CURVE).unwrap();
+        assert_eq!(light.read_lux().unwrap(), 42.5);
+
+        // the raw value is used without scale and offset when there are none
+        fs::remove_file(device.join("in_illuminance_input")).unwrap();
+        fs::write(device.join("in_illuminance_raw"), "200\n").unwrap();
+        assert_eq!(light.read_lux().unwrap(), 200.0);
+
+        // otherwise the offset is added before scaling, as in the IIO ABI
+        fs::write(device.join("in_illuminance_scale"), "0.5\n").unwrap();
+        fs::write(device.join("in_illuminance_offset"), "10\n").unwrap();
+        assert_eq!(light.read_lux().unwrap(), 105.0);
+    }
+
+    #[test]
+    fn interpolates_between_points() {
+        assert_eq!(interpolate(&CURVE, -5.0), 10);
+        assert_eq!(interpolate(&CURVE, 0.0), 10);
+        assert_eq!(interpolate(&CURVE, 50.0), 60);
+        assert_eq!(interpolate(&CURVE, 100.0), 110);
+        assert_eq!(interpolate(&CURVE, 550.0), 155);
+        assert_eq!(interpolate(&CURVE, 5000.0), 200);
+        assert_eq!(interpolate(&[], 50.0), 0);
+    }
+
+    #[test]
+    fn small_changes_are_ignored() {
+        let sysfs = FakeSysfs::new("hysteresis");
+        let device = sysfs.device("iio:device0", &[("in_illuminance_input", "100")]);
+        let mut light = AmbientLight::new(sysfs.root(), &CURVE).unwrap();
+        assert_eq!(light.update(), Some(110));
+        // nothing new before the next sample is due
+        assert_eq!(light.update(), None);
+        // 110 lux only moves the average to 103, within 15% of 100
+        assert_eq!(sample(&mut light, &device, "110"), None);
+        // a lamp moves it to 372.1
+        assert_eq!(sample(&mut light, &device, "1000"), Some(137));
+    }
+}
@@ -0,0 +1,114 @@
+use anyhow::{Result, anyhow};
+use cairo::{Format, ImageSurface};
+use image::{
+    imageops::{resize, FilterType},
+    io::Reader,
+    DynamicImage,
+};
+
+// a few tracks are enough to flip between players without decoding again
+const CAPACITY: usize = 8;
+
+// Decodes "%20" style escapes of file URLs
+fn percent_decode(path: &str) -> String {
+    let bytes = path.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = bytes.get(i + 1..i + 3)
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match (bytes[i], escaped) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            (byte, _) => {
+                decoded.push(byte);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+// Only local art is shown, the file has to be readable by the daemon user
+fn load(url: &str) -> Result<DynamicImage> {
+    let path = url.strip_prefix("file://").ok_or(anyhow!("not a file:// URL"))?;
+    // the format is guessed from the contents, players often omit extensions
+    Ok(Reader::open(percent_decode(path))?.with_guessed_format()?.decode()?)
+}
+
+// Scales the art once, to a surface cairo can paint on every redraw
+fn scale(art: &DynamicImage, size: u32) -> Result<ImageSurface> {
+    let scaled = resize(art, size, size, FilterType::Lanczos3);
+    let mut surface = ImageSurface::create(Format::ARgb32, size as i32, size as i32)?;
+    let stride = surface.stride() as usize;
+    let mut data = surface.data()?;
+    for (x, y, pixel) in scaled.enumerate_pixels() {
+        let [r, g, b, a] = pixel.0;
+        // cairo takes premultiplied alpha in native byte order
+        let premultiply = |c: u8| (c as u32 * a as u32 / 255) as u8;
+        let argb = u32::from_be_bytes([a, premultiply(r), premultiply(g), premultiply(b)]);
+        let offset = y as usize * stride + x as usize * 4;
+        data[offset..offset + 4].copy_from_slice(&argb.to_ne_bytes());
+    }
+    drop(data);
+    Ok(surface)
+}
+
+// Album art by URL and size, most recently used last
+pub struct ArtCache {
+    entries: Vec<(String, u32, Option<ImageSurface>)>,
+}
+
+impl ArtCache {
+    pub fn new() -> ArtCache {
+        ArtCache { entries: Vec::new() }
+    }
+    // Failures are remembered as well so broken art is not retried every update
+    pub fn get(&mut self, url: &str, size: u32) -> Option<ImageSurface> {
+        if url.is_empty() || size == 0 {
+            return None;
+        }
+        if let Some(index) = self.entries.iter().position(|(u, s, _)| u == url && *s == size) {
+            let entry = self.entries.remove(index);
+            self.entries.push(entry);
+            return self.entries.last().unwrap().2.clone();
+        }
+        let art = load(url)
+            .and_then(|art| scale(&art, size))
+            .map_err(|e| eprintln!("Failed to load album art {}: {}", url, e))
+            .ok();
+        if self.entries.len() >= CAPACITY {
+            self.entries.remove(0);
+        }
+        self.entries.push((url.to_string(), size, art.clone()));
+        art
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_escapes() {
+        assert_eq!(percent_decode("/music/My%20Album/cover.jpg"), "/music/My Album/cover.jpg");
+        assert_eq!(percent_decode("/music/Bj%C3%B6rk/%25.png"), "/music/Björk/%.png");
+        assert_eq!(percent_decode("/plain/path.png"), "/plain/path.png");
+    }
+
+    #[test]
+    fn keeps_invalid_escapes() {
+        assert_eq!(percent_decode("/art/100%"), "/art/100%");
+        assert_eq!(percent_decode("/art/%zz%4"), "/art/%zz%4");
+        assert_eq!(percent_decode("/art/%e2%82"), "/art/\u{fffd}");
+    }
+
+    #[test]
+    fn only_files() {
+        assert!(load("https://example.com/cover.png").is_err());
+        assert!(load("file:///nonexistent/cover.png").is_err());
+    }
+}
@@ -1,36 +1,72 @@
 use std::{
     fs::{File, OpenOptions, self},
     path::{PathBuf, Path},
-    time::Instant,
-    io::Write,
+    time::{Duration, Instant},
+    io::{self, Write},
     cmp::min,
+    sync::mpsc::{self, Sender},
+    thread,
 };
 use anyhow::{Result, anyhow};
 use input::event::{
     Event, switch::{Switch, SwitchEvent, SwitchState},
 };
-use crate::TIMEOUT_MS;
+use crate::BacklightConfig;
 
-fn read_attr(path: &Path, attr: &str) -> u32 {
+const TOUCHBAR_BACKLIGHTS: [&str; 2] = ["display-pipe", "appletb_backlight"];
+// time between brightness steps of a fade
+const FADE_FRAME_TIME: Duration = Duration::from_millis(20);
+
+fn read_attr(path: &Path, attr: &str) -> Result<u32> {
     fs::read_to_string(path.join(attr))
-        .expect(&format!("Failed to read {attr}"))
+        .map_err(|e| anyhow!("Failed to read {attr}: {e}"))?
         .trim()
         .parse::<u32>()
-        .expect(&format!("Failed to parse {attr}"))
+        .map_err(|e| anyhow!("Failed to parse {attr}: {e}"))
 }
 
 fn find_backlight() -> Result<PathBuf> {
     for entry in fs::read_dir("/sys/class/backlight/")? {
         let entry = entry?;
-        if ["display-pipe", "appletb_backlight"].contains(&entry.file_name().to_str().unwrap_or("")) {
+        if TOUCHBAR_BACKLIGHTS.contains(&entry.file_name().to_str().unwrap_or("")) {
             return Ok(entry.path());
         }
     }
     Err(anyhow!("No backlight device found"))
 }
 
-fn set_backlight(mut file: &File, value: u32) {
-    file.write(format!("{}\n", value).as_bytes()).unwrap();
+fn find_display_backlight(name: &str) -> Result<PathBuf> {
+    if !name.is_empty() {
+        return Ok(Path::new("/sys/class/backlight/").join(name));
+    }
+    // without a configured name use the first device that is not the touchbar
+    let mut entries = fs::read_dir("/sys/class/backlight/")?
+        .filter_map(|e| e.ok())
+        .filter(|e| !TOUCHBAR_BACKLIGHTS.contains(&e.file_name().to_str().unwrap_or("")))
+        .map(|e| e.path())
+        .collect::<Vec<_>>();
+    entries.sort();
+    entries.into_iter().next().ok_or(anyhow!("No display backlight device found"))
+}
+
+fn set_backlight(mut file: &File, value: u32) -> io::Result<()> {
+    file.write_all(format!("{}\n", value).as_bytes())
+}
+
+// Writes the bar's brightness on a thread of its own, as a slow backlight
+// driver would otherwise hold up the main loop for every step of a fade.
+// Only the latest of the values sent during a slow write is written.
+fn spawn_writer(file: File) -> Sender<u32> {
+    let (sender, receiver) = mpsc::channel::<u32>();
+    thread::spawn(move || {
+        while let Ok(value) = receiver.recv() {
+            let value = receiver.try_iter().last().unwrap_or(value);
+            if let Err(e) = set_backlight(&file, value) {
+                eprintln!("Failed to set backlight to {}: {}", value, e);
+            }
+        }
+    });
+    sender
 }
 
 pub struct BacklightManager {
@@ -38,26 +74,81 @@ pub struct BacklightManager {
     max_bl: u32,
     current_bl: u32,
     lid_state: SwitchState,
-    bl_file: File
+    bl_writer: Sender<u32>,
+    active_bl: u32,
+    dim_bl: u32,
+    // brightness picked for the ambient light, replaces active_bl
+    ambient_bl: Option<u32>,
+    // None never dims or turns off the bar
+    dim_timeout: Option<Duration>,
+    off_timeout: Option<Duration>,
+    // None switches brightness at once
+    fade_time: Option<Duration>,
+    fade: Option<Fade>,
+    // activity woke the bar from dimming, it brightens without a fade
+    woken: bool,
+}
+
+struct Fade {
+    from: u32,
+    to: u32,
+    start: Instant,
+}
+
+// Zero or negative timeouts disable the step
+fn timeout(seconds: f64) -> Option<Duration> {
+    (seconds > 0.0).then(|| Duration::from_secs_f64(seconds))
 }
 
 impl BacklightManager {
-    pub fn new() -> BacklightManager {
+    pub fn new(config: &BacklightConfig) -> BacklightManager {
         let bl_path = find_backlight().unwrap();
         let bl_file = OpenOptions::new().write(true).open(bl_path.join("brightness")).unwrap();
-        BacklightManager {
-            bl_file,
+        let mut manager = BacklightManager {
+            bl_writer: spawn_writer(bl_file),
             lid_state: SwitchState::Off,
-            max_bl: read_attr(&bl_path, "max_brightness"),
-            current_bl: read_attr(&bl_path, "brightness"),
-            last_active: Instant::now()
-        }
+            max_bl: read_attr(&bl_path, "max_brightness").unwrap(),
+            current_bl: read_attr(&bl_path, "brightness").unwrap(),
+            last_active: Instant::now(),
+            active_bl: 0,
+            dim_bl: 0,
+            ambient_bl: None,
+            dim_timeout: None,
+            off_timeout: None,
+            fade_time: None,
+            fade: None,
+            woken: false,
+        };
+        manager.configure(config);
+        manager
+    }
+    // Takes the levels and timeouts from the config, also on reload
+    pub fn configure(&mut self, config: &BacklightConfig) {
+        let clamp = |name: &str, value: u32| {
+            if value > self.max_bl {
+                eprintln!("Backlight {} brightness {} is above the maximum of {}, using that instead", name, value, self.max_bl);
+            }
+            min(value, self.max_bl)
+        };
+        self.active_bl = clamp("active", config.active_brightness);
+        self.dim_bl = clamp("dim", config.dim_brightness);
+        self.dim_timeout = timeout(config.dim_timeout);
+        self.off_timeout = timeout(config.off_timeout);
+        self.fade_time = timeout(config.fade_time);
+    }
+    fn idle(&self) -> bool {
+        let since_last_active = Instant::now() - self.last_active;
+        [self.dim_timeout, self.off_timeout].into_iter().flatten().any(|t| since_last_active >= t)
+    }
+    // Brightens the bar as if it was used, e.g. for a message to be seen
+    pub fn wake(&mut self) {
+        // also cancels a fade towards dim or off that is under way
+        self.woken |= self.idle();
+        self.last_active = Instant::now();
     }
     pub fn process_event(&mut self, event: &Event) {
         match event {
-            Event::Keyboard(_) | Event::Pointer(_) | Event::Gesture(_) | Event::Touch(_) => {
-                self.last_active = Instant::now();
-            },
+            Event::Keyboard(_) | Event::Pointer(_) | Event::Gesture(_) | Event::Touch(_) => self.wake(),
             Event::Switch(SwitchEvent::Toggle(toggle)) => {
                 match toggle.switch() {
                     Some(Switch::Lid) => {
@@ -73,23 +164,114 @@ impl BacklightManager {
             _ => {}
         }
     }
-    pub fn update_backlight(&mut self) {
-        let since_last_active = (Instant::now() - self.last_active).as_millis() as u64;
-        let new_bl = min(self.max_bl, if self.lid_state == SwitchState::On {
-            0
-        } else if since_last_active < TIMEOUT_MS as u64 {
-            128
-        } else if since_last_active < TIMEOUT_MS as u64 * 2 {
-            1
+    pub fn set_ambient_bl(&mut self, brightness: Option<u32>) {
+        self.ambient_bl = brightness.map(|b| min(b, self.max_bl));
+    }
+    fn target_bl(&self) -> u32 {
+        let since_last_active = Instant::now() - self.last_active;
+        let passed = |timeout: Option<Duration>| timeout.is_some_and(|t| since_last_active >= t);
+        if self.lid_state == SwitchState::On || passed(self.off_timeout) {
+            return 0;
+        }
+        let active_bl = self.ambient_bl.unwrap_or(self.active_bl);
+        if passed(self.dim_timeout) {
+            // dimming never brightens a bar that is dark for the room
+            min(self.dim_bl, active_bl)
         } else {
-            0
-        });
-        if self.current_bl != new_bl {
-            self.current_bl = new_bl;
-            set_backlight(&self.bl_file, self.current_bl);
+            active_bl
         }
     }
+    fn set_bl(&mut self, value: u32) {
+        if self.current_bl != value {
+            self.current_bl = value;
+            let _ = self.bl_writer.send(value);
+        }
+    }
+    // Moves the brightness towards its target, a step at a time while fading
+    pub fn update_backlight(&mut self) {
+        let target = self.target_bl();
+        let woken = std::mem::take(&mut self.woken);
+        let fade_time = match self.fade_time {
+            // closing the lid and waking up by touch take effect at once
+            Some(fade_time) if self.lid_state != SwitchState::On && !woken => fade_time,
+            _ => {
+                self.fade = None;
+                self.set_bl(target);
+                return;
+            }
+        };
+        if self.fade.as_ref().map_or(self.current_bl != target, |fade| fade.to != target) {
+            self.fade = Some(Fade {
+                from: self.current_bl,
+                to: target,
+                start: Instant::now(),
+            });
+        }
+        let Some(fade) = &self.fade else {
+            return;
+        };
+        let progress = ((Instant::now() - fade.start).as_secs_f64() / fade_time.as_secs_f64()).min(1.0);
+        let value = (fade.from as f64 + (fade.to as f64 - fade.from as f64) * progress).round() as u32;
+        if progress >= 1.0 {
+            self.fade = None;
+        }
+        self.set_bl(value);
+    }
+    // Subscribers only hear about the brightness a fade ends at
+    pub fn fading(&self) -> bool {
+        self.fade.is_some()
+    }
     pub fn current_bl(&self) -> u32 {
         self.current_bl
     }
+    // When the next fade step is due, or the bar dims or turns off
+    // without any activity
+    pub fn next_update(&self) -> Option<Instant> {
+        if self.lid_state == SwitchState::On {
+            return None;
+        }
+        let now = Instant::now();
+        if self.fade.is_some() {
+            return Some(now + FADE_FRAME_TIME);
+        }
+        [self.dim_timeout, self.off_timeout]
+            .into_iter()
+            .flatten()
+            .map(|timeout| self.last_active + timeout)
+            .filter(|deadline| *deadline > now)
+            .min()
+    }
+}
+
+// A backlight other than the touchbar's own, e.g. the main display panel.
+// Unlike the touchbar backlight this is opened after dropping privileges,
+// so the brightness attribute has to be writable by the video group, which
+// the udev rule only allows for the built in panel.
+pub struct DisplayBacklight {
+    path: PathBuf,
+    max_bl: u32,
+    bl_file: File,
+}
+
+impl DisplayBacklight {
+    pub fn open(name: &str) -> Result<DisplayBacklight> {
+        let path = find_display_backlight(name)?;
+        let max_bl = read_attr(&path, "max_brightness")?;
+        let bl_file = OpenOptions::new()
+            .write(true)
+            .open(path.join("brightness"))
+            .map_err(|e| anyhow!("Failed to open {}: {e}", path.join("brightness").display()))?;
+        Ok(DisplayBacklight { path, max_bl, bl_file })
+    }
+    // Current brightness in percent
+    pub fn level(&self) -> Result<f64> {
+        Ok(read_attr(&self.path, "brightness")? as f64 / self.max_bl.max(1) as f64 * 100.0)
+    }
+    pub fn set_level(&self, percent: f64) {
+        let value = (percent / 100.0 * self.max_bl as f64).round() as u32;
+        // the panel driver may refuse values, e.g. while the display is off
+        if let Err(e) = set_backlight(&self.bl_file, min(value, self.max_bl)) {
+            eprintln!("Failed to set display brightness: {}", e);
+        }
+    }
 }
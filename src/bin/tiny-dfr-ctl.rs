@@ -0,0 +1,182 @@
+use std::{
+    env,
+    io::{BufRead, BufReader, Write},
+    os::unix::net::UnixStream,
+    process::exit,
+};
+use anyhow::{Result, anyhow};
+use tiny_dfr::protocol::{self, Request, Response, Status};
+
+const USAGE: &str = "Usage: tiny-dfr-ctl [--socket PATH] COMMAND
+
+Commands:
+    layer NAME                  switch to a layer, \"back\" returns to the previous one
+    press BUTTON                tap a button of the active layer
+    set-label BUTTON TEXT       replace the text of a button, by index on the active layer or by name
+    update NAME [OPTIONS]       change the content of the buttons with the given name
+        --text TEXT, --icon ICON, --color #RRGGBB, --badge TEXT
+        --ttl SECONDS           revert to the configured content afterwards
+    message TEXT [SECONDS]      show a message across the bar, an empty text hides it
+    focus APP_ID [CLASS]        report the focused application
+    status [--json]             show the active layer and its buttons, or the response as is
+    subscribe                   print events as newline-delimited JSON until interrupted
+    reload                      reload the configuration";
+
+fn parse_update(name: &str, options: &[String]) -> Result<Request> {
+    let (mut text, mut icon, mut color, mut badge, mut ttl) = (None, None, None, None, None);
+    let mut options = options.iter();
+    while let Some(option) = options.next() {
+        let value = options.next().ok_or(anyhow!("Missing value for {}", option))?.clone();
+        match option.as_str() {
+            "--text" => text = Some(value),
+            "--icon" => icon = Some(value),
+            "--color" => color = Some(value),
+            "--badge" => badge = Some(value),
+            "--ttl" => ttl = Some(value.parse().map_err(|_| anyhow!("Invalid ttl {}", value))?),
+            _ => return Err(anyhow!("Unknown option {}\n\n{}", option, USAGE)),
+        }
+    }
+    Ok(Request::Update {
+        button: name.to_string(),
+        text,
+        icon,
+        color,
+        badge,
+        ttl,
+    })
+}
+
+// Returns the request and how many of the arguments it took
+fn parse_args(args: &[String]) -> Result<(Request, usize)> {
+    let arg = |i: usize| args.get(i).ok_or(anyhow!("Missing argument\n\n{}", USAGE));
+    let index = |i: usize| -> Result<usize> {
+        let value = arg(i)?;
+        value.parse().map_err(|_| anyhow!("Invalid button index {}", value))
+    };
+    let request = match arg(0)?.as_str() {
+        "layer" => Request::Layer { layer: arg(1)?.clone() },
+        "press" => Request::Press { button: index(1)? },
+        "set-label" => match arg(1)?.parse() {
+            Ok(button) => Request::SetLabel {
+                button,
+                text: arg(2)?.clone(),
+            },
+            Err(_) => Request::Update {
+                button: arg(1)?.clone(),
+                text: Some(arg(2)?.clone()),
+                icon: None,
+                color: None,
+                badge: None,
+                ttl: None,
+            },
+        },
+        "update" => parse_update(arg(1)?, &args[2..])?,
+        "message" => Request::Message {
+            text: arg(1)?.clone(),
+            timeout: match args.get(2) {
+                Some(seconds) => seconds.parse().map_err(|_| anyhow!("Invalid timeout {}", seconds))?,
+                None => 3.0,
+            },
+        },
+        "focus" => Request::Focus {
+            app_id: arg(1)?.clone(),
+            class: args.get(2).cloned().unwrap_or_default(),
+        },
+        "status" => Request::Status,
+        "subscribe" => Request::Subscribe,
+        "reload" => Request::Reload,
+        command => return Err(anyhow!("Unknown command {}\n\n{}", command, USAGE)),
+    };
+    let used = match request {
+        Request::Status if args.get(1).is_some_and(|a| a == "--json") => 2,
+        Request::Status | Request::Subscribe | Request::Reload => 1,
+        Request::Layer { .. } | Request::Press { .. } => 2,
+        Request::SetLabel { .. } => 3,
+        Request::Update { .. } if args[0] == "set-label" => 3,
+        Request::Update { .. } => args.len(),
+        Request::Message { .. } | Request::Focus { .. } => args.len().min(3),
+    };
+    Ok((request, used))
+}
+
+fn read_response(reader: &mut BufReader<UnixStream>) -> Result<Response> {
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    if line.is_empty() {
+        // the daemon closes connections of clients it does not allow
+        return Err(anyhow!("Connection closed by tiny-dfr, check allowed_users and allowed_groups"));
+    }
+    Ok(serde_json::from_str(&line)?)
+}
+
+fn send(socket: &str, request: &Request) -> Result<(Response, BufReader<UnixStream>)> {
+    let mut stream = UnixStream::connect(socket).map_err(|e| anyhow!("Failed to connect to {}: {}", socket, e))?;
+    let mut message = serde_json::to_vec(request)?;
+    message.push(b'\n');
+    stream.write_all(&message)?;
+    let mut reader = BufReader::new(stream);
+    Ok((read_response(&mut reader)?, reader))
+}
+
+fn print_events(reader: BufReader<UnixStream>) -> Result<()> {
+    for line in reader.lines() {
+        println!("{}", line?);
+    }
+    Err(anyhow!("Connection closed by tiny-dfr"))
+}
+
+fn print_status(status: &Status) {
+    match &status.layer_name {
+        Some(name) => println!("layer: {} ({})", status.layer, name),
+        None => println!("layer: {}", status.layer),
+    }
+    for (i, button) in status.buttons.iter().enumerate() {
+        let label = button.label.as_deref().unwrap_or("<icon>");
+        let name = button.name.as_ref().map(|name| format!(" [{}]", name)).unwrap_or_default();
+        let active = if button.active { " (pressed)" } else { "" };
+        println!("{:>3}: {}{}{}", i, label, name, active);
+    }
+}
+
+fn run() -> Result<()> {
+    let mut args = env::args().skip(1).collect::<Vec<_>>();
+    let mut socket = protocol::CONTROL_SOCKET.to_string();
+    // the socket option comes before the command
+    while args.first().is_some_and(|a| a == "--socket") {
+        if args.len() < 2 {
+            return Err(anyhow!("Missing socket path\n\n{}", USAGE));
+        }
+        socket = args.remove(1);
+        args.remove(0);
+    }
+    if args.is_empty() || args[0] == "--help" || args[0] == "-h" {
+        println!("{}", USAGE);
+        return Ok(());
+    }
+    let (request, used) = parse_args(&args)?;
+    let json = matches!(request, Request::Status) && used == 2;
+    if let Some(arg) = args.get(used) {
+        return Err(anyhow!("Unexpected argument {}\n\n{}", arg, USAGE));
+    }
+    let subscribe = matches!(request, Request::Subscribe);
+    let (response, reader) = send(&socket, &request)?;
+    if subscribe && response.ok {
+        return print_events(reader);
+    }
+    if json {
+        println!("{}", serde_json::to_string_pretty(&response)?);
+    } else if let Some(status) = &response.status {
+        print_status(status);
+    }
+    if !response.ok {
+        return Err(anyhow!(response.error.unwrap_or("Request failed".to_string())));
+    }
+    Ok(())
+}
+
+fn main() {
+    if let Err(e) = run() {
+        eprintln!("{}", e);
+        exit(1);
+    }
+}
@@ -0,0 +1,162 @@
+use std::{
+    iter,
+    os::fd::{AsRawFd, RawFd},
+    process::{Child, ChildStdout, Command, Stdio},
+    time::{Duration, Instant},
+};
+use anyhow::Result;
+use nix::fcntl::{fcntl, FcntlArg, OFlag};
+use serde::Deserialize;
+use crate::{lines::LineReader, parse_color, Color};
+
+#[derive(Deserialize)]
+struct JsonOutput {
+    #[serde(default)]
+    text: String,
+    icon: Option<String>,
+    color: Option<String>,
+}
+
+pub struct CommandUpdate {
+    pub text: String,
+    pub icon: Option<String>,
+    pub color: Option<Color>,
+}
+
+pub struct CommandOutput {
+    command: String,
+    interval: Duration,
+    continuous: bool,
+    json: bool,
+    child: Option<Child>,
+    stdout: Option<ChildStdout>,
+    buffer: LineReader,
+    last_line: String,
+    next_run: Instant,
+    pub icon_theme: String,
+}
+
+fn parse_line(line: &str, json: bool) -> CommandUpdate {
+    if json {
+        match serde_json::from_str::<JsonOutput>(line) {
+            Ok(output) => {
+                return CommandUpdate {
+                    text: output.text,
+                    icon: output.icon,
+                    color: output.color.as_deref().and_then(parse_color),
+                }
+            }
+            Err(e) => eprintln!("Failed to parse command output as JSON: {}", e),
+        }
+    }
+    CommandUpdate {
+        text: line.to_string(),
+        icon: None,
+        color: None,
+    }
+}
+
+impl CommandOutput {
+    pub fn new(command: &str, interval: f64, continuous: bool, json: bool, icon_theme: &str) -> CommandOutput {
+        CommandOutput {
+            command: command.to_string(),
+            interval: Duration::from_secs_f64(interval.max(0.1)),
+            continuous,
+            json,
+            child: None,
+            stdout: None,
+            buffer: LineReader::new(),
+            last_line: String::new(),
+            next_run: Instant::now(),
+            icon_theme: icon_theme.to_string(),
+        }
+    }
+    fn spawn(&mut self) -> Result<()> {
+        let mut child = Command::new("/bin/sh")
+            .arg("-c")
+            .arg(&self.command)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .spawn()?;
+        let stdout = child.stdout.take().unwrap();
+        fcntl(stdout.as_raw_fd(), FcntlArg::F_SETFL(OFlag::O_NONBLOCK))?;
+        self.buffer.clear();
+        self.stdout = Some(stdout);
+        self.child = Some(child);
+        Ok(())
+    }
+    // Drains whatever the child has written so far without blocking
+    fn read_output(&mut self) -> Option<CommandUpdate> {
+        if let Some(stdout) = &mut self.stdout {
+            let open = self.buffer.read_from(stdout).unwrap_or_else(|e| {
+                eprintln!("Failed to read output of \"{}\": {}", self.command, e);
+                false
+            });
+            if !open {
+                self.stdout = None;
+                self.buffer.finish();
+            }
+        }
+        let lines = iter::from_fn(|| self.buffer.next_line()).collect::<Vec<_>>();
+        let line = lines.iter().rfind(|l| !l.trim().is_empty())?.trim_end();
+        if line == self.last_line {
+            return None;
+        }
+        self.last_line = line.to_string();
+        Some(parse_line(line, self.json))
+    }
+    fn reap(&mut self) {
+        if self.stdout.is_some() {
+            return;
+        }
+        if let Some(child) = &mut self.child {
+            match child.try_wait() {
+                Ok(None) => return,
+                Ok(Some(_)) => {}
+                Err(e) => eprintln!("Failed to wait for \"{}\": {}", self.command, e),
+            }
+            self.child = None;
+        }
+    }
+    // Starts the command when it is due and collects its output,
+    // returns the latest line if a new one arrived.
+    pub fn update(&mut self) -> Option<CommandUpdate> {
+        let now = Instant::now();
+        if self.child.is_none() && now >= self.next_run {
+            self.next_run = now + self.interval;
+            if let Err(e) = self.spawn() {
+                eprintln!("Failed to run \"{}\": {}", self.command, e);
+            }
+        }
+        let update = self.read_output();
+        self.reap();
+        // retry reaping a child that closed its output later, and
+        // throttle restarts of continuous commands
+        if (self.child.is_some() || self.continuous) && self.next_run < now {
+            self.next_run = now + self.interval;
+        }
+        update
+    }
+    pub fn command(&self) -> &str {
+        &self.command
+    }
+    pub fn next_update(&self) -> Option<Instant> {
+        match self.stdout {
+            // the child's output wakes up the poll loop
+            Some(_) => None,
+            None => Some(self.next_run),
+        }
+    }
+    pub fn poll_fd(&self) -> Option<RawFd> {
+        self.stdout.as_ref().map(|stdout| stdout.as_raw_fd())
+    }
+}
+
+impl Drop for CommandOutput {
+    fn drop(&mut self) {
+        if let Some(child) = &mut self.child {
+            let _ = child.kill();
+            let _ = child.wait();
+        }
+    }
+}
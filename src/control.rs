@@ -0,0 +1,179 @@
+use std::{
+    fs,
+    io::{ErrorKind, Write},
+    os::{
+        fd::{AsRawFd, RawFd},
+        unix::{
+            fs::PermissionsExt,
+            net::{UnixListener, UnixStream},
+        },
+    },
+    path::Path,
+};
+use anyhow::Result;
+use nix::{
+    sys::socket::{getsockopt, sockopt::PeerCredentials},
+    unistd::{Gid, Group, Uid, User},
+};
+use crate::{
+    lines::LineReader,
+    protocol::{Event, Request, Response},
+};
+
+// a client that sends more than this without a newline is dropped
+const MAX_REQUEST_LEN: usize = 64 * 1024;
+// as is a subscriber that stops reading its events
+const MAX_BACKLOG: usize = 256 * 1024;
+
+struct Client {
+    stream: UnixStream,
+    buffer: LineReader,
+    outgoing: Vec<u8>,
+    subscribed: bool,
+}
+
+impl Client {
+    // Queues a line and writes as much as possible without blocking,
+    // returns false if the client has to be dropped
+    fn send<T: serde::Serialize>(&mut self, message: &T) -> bool {
+        self.outgoing.extend_from_slice(&serde_json::to_vec(message).unwrap());
+        self.outgoing.push(b'\n');
+        self.flush()
+    }
+    fn flush(&mut self) -> bool {
+        while !self.outgoing.is_empty() {
+            match self.stream.write(&self.outgoing) {
+                Ok(0) => return false,
+                Ok(n) => {
+                    self.outgoing.drain(..n);
+                }
+                Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(_) => return false,
+            }
+        }
+        self.outgoing.len() <= MAX_BACKLOG
+    }
+}
+
+// Root is always allowed, others have to be listed or be in a listed group,
+// either by the gid of their process if known or by their group memberships.
+// The D-Bus service checks its callers the same way.
+pub fn allowed(uid: u32, gid: Option<u32>, allowed_users: &[String], allowed_groups: &[String]) -> bool {
+    if uid == 0 {
+        return true;
+    }
+    let Ok(Some(user)) = User::from_uid(Uid::from_raw(uid)) else {
+        return false;
+    };
+    if allowed_users.contains(&user.name) {
+        return true;
+    }
+    allowed_groups.iter().any(|name| {
+        let Ok(Some(group)) = Group::from_name(name) else {
+            return false;
+        };
+        gid.map(Gid::from_raw) == Some(group.gid) || group.gid == user.gid || group.mem.contains(&user.name)
+    })
+}
+
+pub struct ControlServer {
+    listener: UnixListener,
+    clients: Vec<Client>,
+    allowed_users: Vec<String>,
+    allowed_groups: Vec<String>,
+}
+
+impl ControlServer {
+    // Binds the socket, this has to happen before dropping privileges
+    pub fn bind(path: &str, allowed_users: &[String], allowed_groups: &[String]) -> Result<ControlServer> {
+        let path = Path::new(path);
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        // a previous instance may have left its socket behind
+        let _ = fs::remove_file(path);
+        let listener = UnixListener::bind(path)?;
+        listener.set_nonblocking(true)?;
+        // everyone may connect, clients are checked with SO_PEERCRED
+        fs::set_permissions(path, fs::Permissions::from_mode(0o666))?;
+        Ok(ControlServer {
+            listener,
+            clients: Vec::new(),
+            allowed_users: allowed_users.to_vec(),
+            allowed_groups: allowed_groups.to_vec(),
+        })
+    }
+    pub fn set_allowed(&mut self, allowed_users: &[String], allowed_groups: &[String]) {
+        self.allowed_users = allowed_users.to_vec();
+        self.allowed_groups = allowed_groups.to_vec();
+    }
+    fn permitted(&self, stream: &UnixStream) -> bool {
+        let Ok(creds) = getsockopt(stream.as_raw_fd(), PeerCredentials) else {
+            return false;
+        };
+        allowed(creds.uid(), Some(creds.gid()), &self.allowed_users, &self.allowed_groups)
+    }
+    fn accept(&mut self) {
+        loop {
+            let stream = match self.listener.accept() {
+                Ok((stream, _)) => stream,
+                Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(e) => {
+                    eprintln!("Failed to accept control connection: {}", e);
+                    break;
+                }
+            };
+            if !self.permitted(&stream) {
+                // dropping the stream closes the connection
+                continue;
+            }
+            if let Err(e) = stream.set_nonblocking(true) {
+                eprintln!("Failed to set up control connection: {}", e);
+                continue;
+            }
+            self.clients.push(Client {
+                stream,
+                buffer: LineReader::new(),
+                outgoing: Vec::new(),
+                subscribed: false,
+            });
+        }
+    }
+    // Accepts new clients and answers every complete request with the handler
+    pub fn process<F>(&mut self, mut handler: F)
+    where
+        F: FnMut(Request) -> Response,
+    {
+        self.accept();
+        self.clients.retain_mut(|client| {
+            let open = client.buffer.read_from(&mut client.stream).unwrap_or(false);
+            while let Some(line) = client.buffer.next_line() {
+                if line.trim().is_empty() {
+                    continue;
+                }
+                let response = match serde_json::from_str::<Request>(&line) {
+                    Ok(request) => {
+                        client.subscribed |= matches!(request, Request::Subscribe);
+                        handler(request)
+                    }
+                    Err(e) => Response::error(&format!("Invalid request: {}", e)),
+                };
+                if !client.send(&response) {
+                    return false;
+                }
+            }
+            open && client.flush() && client.buffer.buffered().len() <= MAX_REQUEST_LEN
+        });
+    }
+    pub fn broadcast(&mut self, event: &Event) {
+        self.clients.retain_mut(|client| !client.subscribed || client.send(event));
+    }
+    pub fn poll_fds(&self) -> Vec<RawFd> {
+        self.clients
+            .iter()
+            .map(|client| client.stream.as_raw_fd())
+            .chain([self.listener.as_raw_fd()])
+            .collect()
+    }
+}
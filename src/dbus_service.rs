@@ -0,0 +1,348 @@
+// D-Bus interface to the same requests and events as the control socket,
+// for desktop components that would rather not speak JSON over a socket.
+use std::{os::fd::RawFd, time::Duration};
+use anyhow::{Result, anyhow};
+use dbus::{
+    arg::{prop_cast, PropMap},
+    channel::{BusType, Channel},
+    message::MessageType,
+    Message, MethodErr,
+};
+use crate::{
+    control,
+    protocol::{Event, Request, Response},
+};
+
+pub const BUS_NAME: &str = "org.asahi.TinyDfr1";
+const OBJECT_PATH: &str = "/org/asahi/TinyDfr1";
+const INTERFACE: &str = "org.asahi.TinyDfr1";
+const INTROSPECTABLE: &str = "org.freedesktop.DBus.Introspectable";
+const DBUS: &str = "org.freedesktop.DBus";
+const DBUS_PATH: &str = "/org/freedesktop/DBus";
+const ACCESS_DENIED: &str = "org.freedesktop.DBus.Error.AccessDenied";
+const CALL_TIMEOUT: Duration = Duration::from_secs(5);
+
+const INTROSPECTION: &str = r#"<!DOCTYPE node PUBLIC "-//freedesktop//DTD D-BUS Object Introspection 1.0//EN"
+ "http://www.freedesktop.org/standards/dbus/1.0/introspect.dtd">
+<node>
+  <interface name="org.asahi.TinyDfr1">
+    <method name="SwitchLayer">
+      <arg name="layer" type="s" direction="in"/>
+    </method>
+    <method name="SetButton">
+      <arg name="name" type="s" direction="in"/>
+      <arg name="content" type="a{sv}" direction="in"/>
+    </method>
+    <method name="Press">
+      <arg name="button" type="u" direction="in"/>
+    </method>
+    <method name="ShowMessage">
+      <arg name="text" type="s" direction="in"/>
+      <arg name="timeout" type="d" direction="in"/>
+    </method>
+    <method name="Reload"/>
+    <method name="GetStatus">
+      <arg name="layer" type="u" direction="out"/>
+      <arg name="layer_name" type="s" direction="out"/>
+      <arg name="buttons" type="a(ssb)" direction="out"/>
+    </method>
+    <signal name="LayerChanged">
+      <arg name="layer" type="u"/>
+      <arg name="layer_name" type="s"/>
+    </signal>
+    <signal name="ButtonPressed">
+      <arg name="layer" type="u"/>
+      <arg name="button" type="u"/>
+      <arg name="name" type="s"/>
+    </signal>
+    <signal name="ButtonReleased">
+      <arg name="layer" type="u"/>
+      <arg name="button" type="u"/>
+      <arg name="name" type="s"/>
+    </signal>
+    <signal name="BacklightChanged">
+      <arg name="brightness" type="u"/>
+    </signal>
+    <signal name="ConfigReloaded"/>
+  </interface>
+  <interface name="org.freedesktop.DBus.Introspectable">
+    <method name="Introspect">
+      <arg name="xml" type="s" direction="out"/>
+    </method>
+  </interface>
+</node>
+"#;
+
+// SetButton takes the same fields as the update command, all optional
+fn update_request(msg: &Message) -> Result<Request, MethodErr> {
+    let (button, content): (String, PropMap) = msg.read2()?;
+    let string = |key: &str| prop_cast::<String>(&content, key).cloned();
+    Ok(Request::Update {
+        button,
+        text: string("text"),
+        icon: string("icon"),
+        color: string("color"),
+        badge: string("badge"),
+        ttl: prop_cast::<f64>(&content, "ttl").copied(),
+    })
+}
+
+fn parse_request(msg: &Message) -> Result<Request, MethodErr> {
+    let member = msg.member().map(|m| m.to_string()).unwrap_or_default();
+    let request = match member.as_str() {
+        "SwitchLayer" => Request::Layer { layer: msg.read1()? },
+        "SetButton" => update_request(msg)?,
+        "Press" => Request::Press {
+            button: msg.read1::<u32>()? as usize,
+        },
+        "ShowMessage" => {
+            let (text, timeout) = msg.read2()?;
+            Request::Message { text, timeout }
+        }
+        "Reload" => Request::Reload,
+        "GetStatus" => Request::Status,
+        _ => return Err(MethodErr::no_method(&member)),
+    };
+    Ok(request)
+}
+
+fn reply(msg: &Message, response: Response) -> Message {
+    if !response.ok {
+        let error = response.error.unwrap_or("Request failed".to_string());
+        return MethodErr::failed(&error).to_message(msg);
+    }
+    match response.status {
+        Some(status) => {
+            let buttons = status
+                .buttons
+                .into_iter()
+                .map(|b| (b.name.unwrap_or_default(), b.label.unwrap_or_default(), b.active))
+                .collect::<Vec<_>>();
+            msg.method_return()
+                .append3(status.layer as u32, status.layer_name.unwrap_or_default(), buttons)
+        }
+        None => msg.method_return(),
+    }
+}
+
+fn signal(event: &Event) -> Message {
+    let new = |name: &str| Message::new_signal(OBJECT_PATH, INTERFACE, name).unwrap();
+    let name = |name: &Option<String>| name.clone().unwrap_or_default();
+    match event {
+        Event::ButtonPressed { layer, button, name: n } => {
+            new("ButtonPressed").append3(*layer as u32, *button as u32, name(n))
+        }
+        Event::ButtonReleased { layer, button, name: n } => {
+            new("ButtonReleased").append3(*layer as u32, *button as u32, name(n))
+        }
+        Event::LayerChanged { layer, layer_name } => new("LayerChanged").append2(*layer as u32, name(layer_name)),
+        Event::BacklightChanged { brightness } => new("BacklightChanged").append1(*brightness),
+        Event::ConfigReloaded => new("ConfigReloaded"),
+    }
+}
+
+pub struct DbusService {
+    channel: Channel,
+    // the bus policy lets everyone call the service, callers are checked
+    // against the allow lists of the control socket instead
+    allowed_users: Vec<String>,
+    allowed_groups: Vec<String>,
+}
+
+impl DbusService {
+    // Connects to the bus at the given address, or the system bus when
+    // empty, and claims the service name
+    pub fn connect(address: &str, allowed_users: &[String], allowed_groups: &[String]) -> Result<DbusService> {
+        let mut channel = match address {
+            "" => Channel::get_private(BusType::System)?,
+            address => {
+                let mut channel = Channel::open_private(address)?;
+                channel.register()?;
+                channel
+            }
+        };
+        channel.set_watch_enabled(true);
+        let request = Message::new_method_call(DBUS, DBUS_PATH, DBUS, "RequestName")
+            .map_err(|e| anyhow!(e))?
+            // do not queue up behind another instance
+            .append2(BUS_NAME, 4u32);
+        let owner: u32 = channel.send_with_reply_and_block(request, CALL_TIMEOUT)?.read1()?;
+        if owner != 1 {
+            return Err(anyhow!("{} is already owned by another process", BUS_NAME));
+        }
+        Ok(DbusService {
+            channel,
+            allowed_users: allowed_users.to_vec(),
+            allowed_groups: allowed_groups.to_vec(),
+        })
+    }
+    pub fn set_allowed(&mut self, allowed_users: &[String], allowed_groups: &[String]) {
+        self.allowed_users = allowed_users.to_vec();
+        self.allowed_groups = allowed_groups.to_vec();
+    }
+    // Asks the bus which user sent the message
+    fn permitted(&self, msg: &Message) -> bool {
+        let Some(sender) = msg.sender() else {
+            return false;
+        };
+        let Ok(request) = Message::new_method_call(DBUS, DBUS_PATH, DBUS, "GetConnectionUnixUser") else {
+            return false;
+        };
+        let uid = self
+            .channel
+            .send_with_reply_and_block(request.append1(&*sender), CALL_TIMEOUT)
+            .and_then(|reply| reply.read1::<u32>().map_err(|e| e.into()));
+        uid.is_ok_and(|uid| control::allowed(uid, None, &self.allowed_users, &self.allowed_groups))
+    }
+    // Answers pending method calls with the handler, fails once the bus is gone
+    pub fn process<F>(&mut self, mut handler: F) -> Result<()>
+    where
+        F: FnMut(Request) -> Response,
+    {
+        self.channel
+            .read_write(Some(Duration::ZERO))
+            .map_err(|_| anyhow!("Connection to the bus was lost"))?;
+        while let Some(msg) = self.channel.pop_message() {
+            if msg.msg_type() != MessageType::MethodCall {
+                continue;
+            }
+            let path = msg.path().map(|p| p.to_string()).unwrap_or_default();
+            let interface = msg.interface().map(|i| i.to_string());
+            let response = match interface.as_deref() {
+                _ if path != OBJECT_PATH => MethodErr::no_path(&path).to_message(&msg),
+                Some(INTROSPECTABLE) => msg.method_return().append1(INTROSPECTION),
+                Some(INTERFACE) | None if !self.permitted(&msg) => {
+                    MethodErr::from((ACCESS_DENIED, "Not allowed to control tiny-dfr")).to_message(&msg)
+                }
+                Some(INTERFACE) | None => match parse_request(&msg) {
+                    Ok(request) => reply(&msg, handler(request)),
+                    Err(e) => e.to_message(&msg),
+                },
+                Some(interface) => MethodErr::no_interface(&interface).to_message(&msg),
+            };
+            if !msg.get_no_reply() {
+                let _ = self.channel.send(response);
+            }
+        }
+        self.channel.flush();
+        Ok(())
+    }
+    pub fn broadcast(&mut self, event: &Event) {
+        let _ = self.channel.send(signal(event));
+        self.channel.flush();
+    }
+    pub fn poll_fd(&self) -> RawFd {
+        self.channel.watch().fd
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+    use dbus::{blocking::Connection, message::MatchRule};
+    use nix::unistd::{Uid, User};
+    use crate::test_bus::TestBus;
+
+    fn current_user() -> Vec<String> {
+        vec![User::from_uid(Uid::current()).unwrap().unwrap().name]
+    }
+
+    // Makes a call from another connection while the service answers it,
+    // returns its result and the requests the handler got
+    fn serve<T: Send + 'static>(
+        bus: &TestBus,
+        service: &mut DbusService,
+        call: impl FnOnce(Connection) -> T + Send + 'static,
+    ) -> (T, Vec<Request>) {
+        let client = Connection::from(bus.channel());
+        let client = thread::spawn(move || call(client));
+        let mut requests = Vec::new();
+        while !client.is_finished() {
+            service
+                .process(|request| {
+                    requests.push(request);
+                    Response::ok()
+                })
+                .unwrap();
+            thread::sleep(Duration::from_millis(5));
+        }
+        (client.join().unwrap(), requests)
+    }
+
+    fn call<A: dbus::arg::AppendAll + Send + 'static>(
+        method: &'static str,
+        args: A,
+    ) -> impl FnOnce(Connection) -> Result<(), dbus::Error> {
+        move |client| {
+            client
+                .with_proxy(BUS_NAME, OBJECT_PATH, CALL_TIMEOUT)
+                .method_call(INTERFACE, method, args)
+        }
+    }
+
+    #[test]
+    fn calls_become_requests() {
+        let Some(bus) = TestBus::start() else { return };
+        let mut service = DbusService::connect(&bus.address, &current_user(), &[]).unwrap();
+        let (result, requests) = serve(&bus, &mut service, call("SwitchLayer", ("media",)));
+        result.unwrap();
+        assert!(matches!(&requests[..], [Request::Layer { layer }] if layer == "media"));
+
+        let mut content = PropMap::new();
+        content.insert("text".to_string(), dbus::arg::Variant(Box::new("42%".to_string())));
+        content.insert("ttl".to_string(), dbus::arg::Variant(Box::new(2.5f64)));
+        let (result, requests) = serve(&bus, &mut service, call("SetButton", ("volume", content)));
+        result.unwrap();
+        assert!(matches!(
+            &requests[..],
+            [Request::Update { button, text: Some(text), icon: None, ttl: Some(ttl), .. }]
+                if button == "volume" && text == "42%" && *ttl == 2.5
+        ));
+    }
+
+    #[test]
+    fn unknown_methods_fail() {
+        let Some(bus) = TestBus::start() else { return };
+        let mut service = DbusService::connect(&bus.address, &current_user(), &[]).unwrap();
+        let (result, requests) = serve(&bus, &mut service, call("Explode", ()));
+        assert_eq!(result.unwrap_err().name(), Some("org.freedesktop.DBus.Error.UnknownMethod"));
+        assert!(requests.is_empty());
+    }
+
+    #[test]
+    fn callers_not_allowed_are_denied() {
+        // root may always call
+        if Uid::current().is_root() {
+            return;
+        }
+        let Some(bus) = TestBus::start() else { return };
+        let mut service = DbusService::connect(&bus.address, &[], &[]).unwrap();
+        let (result, requests) = serve(&bus, &mut service, call("Reload", ()));
+        assert_eq!(result.unwrap_err().name(), Some(ACCESS_DENIED));
+        assert!(requests.is_empty());
+    }
+
+    #[test]
+    fn events_become_signals() {
+        let Some(bus) = TestBus::start() else { return };
+        let mut service = DbusService::connect(&bus.address, &[], &[]).unwrap();
+        let client = Connection::from(bus.channel());
+        let rule = MatchRule::new_signal(INTERFACE, "LayerChanged");
+        client.add_match_no_cb(&rule.match_str()).unwrap();
+        service.broadcast(&Event::LayerChanged {
+            layer: 1,
+            layer_name: Some("media".to_string()),
+        });
+        let mut received = None;
+        for _ in 0..100 {
+            client.channel().read_write(Some(Duration::from_millis(10))).unwrap();
+            // the bus sends its own signals too
+            if let Some(msg) = client.channel().pop_message().filter(|m| m.interface().as_deref() == Some(INTERFACE)) {
+                received = Some(msg.read2::<u32, String>().unwrap());
+                break;
+            }
+        }
+        assert_eq!(received, Some((1, "media".to_string())));
+    }
+}
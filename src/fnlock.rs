@@ -0,0 +1,206 @@
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{Read, Seek, Write},
+    time::{Duration, Instant},
+};
+use anyhow::Result;
+
+// created by systemd through StateDirectory=
+const STATE_DIR: &str = "/var/lib/tiny-dfr";
+const STATE_FILE: &str = "/var/lib/tiny-dfr/fn-lock";
+const DOUBLE_TAP: Duration = Duration::from_millis(300);
+const LONG_PRESS: Duration = Duration::from_millis(1000);
+
+fn open_state() -> Result<File> {
+    fs::create_dir_all(STATE_DIR)?;
+    Ok(OpenOptions::new().read(true).write(true).create(true).truncate(false).open(STATE_FILE)?)
+}
+
+// Recognizes double taps and long presses of Fn that toggle the lock.
+// Fn used as a modifier, i.e. held while another key is pressed, never
+// toggles anything.
+pub struct FnLock {
+    enabled: bool,
+    locked: bool,
+    file: Option<File>,
+    pressed_at: Option<Instant>,
+    last_tap: Option<Instant>,
+    // the current press already toggled the lock or was used as a modifier
+    consumed: bool,
+}
+
+impl FnLock {
+    // The state file is kept open so it can still be written after dropping privileges
+    pub fn new(enabled: bool) -> FnLock {
+        let mut file = match open_state() {
+            Ok(file) => Some(file),
+            Err(e) => {
+                eprintln!("Failed to open Fn lock state, it will not persist: {}", e);
+                None
+            }
+        };
+        let mut state = String::new();
+        if let Some(file) = &mut file {
+            let _ = file.read_to_string(&mut state);
+        }
+        FnLock {
+            enabled,
+            locked: state.trim() == "1",
+            file,
+            pressed_at: None,
+            last_tap: None,
+            consumed: false,
+        }
+    }
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+    }
+    pub fn locked(&self) -> bool {
+        self.enabled && self.locked
+    }
+    fn toggle(&mut self) {
+        self.locked = !self.locked;
+        self.consumed = true;
+        self.last_tap = None;
+        let Some(file) = &mut self.file else {
+            return;
+        };
+        let state: &[u8] = if self.locked { b"1\n" } else { b"0\n" };
+        let result = file.set_len(0).and_then(|_| file.rewind()).and_then(|_| file.write_all(state));
+        if let Err(e) = result {
+            eprintln!("Failed to save Fn lock state: {}", e);
+        }
+    }
+    // The following return true if the lock was toggled
+    pub fn press(&mut self) -> bool {
+        if !self.enabled {
+            return false;
+        }
+        let now = Instant::now();
+        self.pressed_at = Some(now);
+        self.consumed = false;
+        let double_tap = self.last_tap.is_some_and(|tap| now - tap < DOUBLE_TAP);
+        if double_tap && !self.locked {
+            self.toggle();
+            return true;
+        }
+        false
+    }
+    pub fn release(&mut self) -> bool {
+        if self.pressed_at.take().is_none() {
+            return false;
+        }
+        if self.consumed {
+            self.last_tap = None;
+            return false;
+        }
+        // a single tap unlocks, while unlocked it may start a double tap
+        if self.locked {
+            self.toggle();
+            return true;
+        }
+        self.last_tap = Some(Instant::now());
+        false
+    }
+    pub fn other_key(&mut self) {
+        self.consumed = self.pressed_at.is_some();
+        self.last_tap = None;
+    }
+    pub fn update(&mut self) -> bool {
+        let Some(pressed_at) = self.pressed_at else {
+            return false;
+        };
+        if !self.consumed && Instant::now() >= pressed_at + LONG_PRESS {
+            self.toggle();
+            return true;
+        }
+        false
+    }
+    pub fn next_update(&self) -> Option<Instant> {
+        self.pressed_at.filter(|_| !self.consumed).map(|pressed_at| pressed_at + LONG_PRESS)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // without a state file, tests must not touch /var/lib
+    fn unlocked() -> FnLock {
+        FnLock {
+            enabled: true,
+            locked: false,
+            file: None,
+            pressed_at: None,
+            last_tap: None,
+            consumed: false,
+        }
+    }
+
+    fn tap(lock: &mut FnLock) -> bool {
+        let pressed = lock.press();
+        lock.release() || pressed
+    }
+
+    #[test]
+    fn double_tap_locks() {
+        let mut lock = unlocked();
+        assert!(!tap(&mut lock));
+        assert!(!lock.locked());
+        assert!(tap(&mut lock));
+        assert!(lock.locked());
+        // a single tap unlocks again
+        assert!(tap(&mut lock));
+        assert!(!lock.locked());
+    }
+
+    #[test]
+    fn slow_taps_do_nothing() {
+        let mut lock = unlocked();
+        tap(&mut lock);
+        lock.last_tap = Some(Instant::now() - DOUBLE_TAP);
+        assert!(!tap(&mut lock));
+        assert!(!lock.locked());
+    }
+
+    #[test]
+    fn long_press_toggles() {
+        let mut lock = unlocked();
+        lock.press();
+        assert!(!lock.update());
+        assert!(lock.next_update().is_some());
+        lock.pressed_at = Some(Instant::now() - LONG_PRESS);
+        assert!(lock.update());
+        assert!(lock.locked());
+        // only once per press, and releasing it does not unlock
+        assert!(lock.next_update().is_none());
+        assert!(!lock.update());
+        assert!(!lock.release());
+        assert!(lock.locked());
+    }
+
+    #[test]
+    fn other_keys_cancel() {
+        let mut lock = unlocked();
+        lock.press();
+        lock.other_key();
+        lock.pressed_at = Some(Instant::now() - LONG_PRESS);
+        assert!(!lock.update());
+        assert!(!lock.release());
+        // nor does a modifier use start a double tap
+        assert!(!tap(&mut lock));
+        // and keys typed between two taps break them up
+        lock.other_key();
+        assert!(!tap(&mut lock));
+        assert!(!lock.locked());
+    }
+
+    #[test]
+    fn disabled() {
+        let mut lock = unlocked();
+        lock.set_enabled(false);
+        assert!(!tap(&mut lock));
+        assert!(!tap(&mut lock));
+        assert!(!lock.locked());
+    }
+}
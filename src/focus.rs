@@ -0,0 +1,269 @@
+use std::{
+    fs,
+    io::Write,
+    os::{
+        fd::{AsRawFd, RawFd},
+        unix::{fs::MetadataExt, net::UnixStream},
+    },
+    path::{Path, PathBuf},
+    time::{Instant, SystemTime},
+};
+use anyhow::Result;
+use regex::Regex;
+use serde_json::Value;
+use crate::{
+    lines::LineReader,
+    reconnect::Reconnect,
+    session::{self, Target},
+};
+
+const IPC_MAGIC: &[u8] = b"i3-ipc";
+const IPC_HEADER_LEN: usize = 14;
+const IPC_SUBSCRIBE: u32 = 2;
+const IPC_GET_TREE: u32 = 4;
+const IPC_EVENT_WINDOW: u32 = 0x80000003;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum FocusSource {
+    // sway and i3 share the same IPC protocol
+    Sway,
+    I3,
+    Hyprland,
+}
+
+impl FocusSource {
+    pub fn from_name(name: &str) -> Option<FocusSource> {
+        match name {
+            "sway" => Some(FocusSource::Sway),
+            "i3" => Some(FocusSource::I3),
+            "hyprland" => Some(FocusSource::Hyprland),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Default, PartialEq)]
+pub struct FocusedApp {
+    // Wayland app_id, empty for X11 windows
+    pub app_id: String,
+    // X11 window class, Hyprland reports the app_id here as well
+    pub class: String,
+}
+
+pub struct FocusRule {
+    pub app_id: Option<Regex>,
+    pub class: Option<Regex>,
+    pub layer: usize,
+}
+
+impl FocusRule {
+    fn matches(&self, app: &FocusedApp) -> bool {
+        let app_id = self.app_id.as_ref().is_some_and(|re| re.is_match(&app.app_id));
+        let class = self.class.as_ref().is_some_and(|re| re.is_match(&app.class));
+        app_id || class
+    }
+}
+
+// Returns the layer of the first rule matching the app
+pub fn matching_layer(rules: &[FocusRule], app: &FocusedApp) -> Option<usize> {
+    rules.iter().find(|rule| rule.matches(app)).map(|rule| rule.layer)
+}
+
+fn newest(paths: Vec<PathBuf>) -> Option<PathBuf> {
+    // stale sockets of earlier sessions are left behind, prefer the latest one
+    paths
+        .into_iter()
+        .max_by_key(|p| fs::metadata(p).and_then(|m| m.modified()).unwrap_or(SystemTime::UNIX_EPOCH))
+}
+
+fn list_dir(dir: &Path) -> Vec<PathBuf> {
+    fs::read_dir(dir)
+        .map(|entries| entries.filter_map(|e| e.ok()).map(|e| e.path()).collect())
+        .unwrap_or_default()
+}
+
+fn file_name(path: &Path) -> &str {
+    path.file_name().and_then(|n| n.to_str()).unwrap_or("")
+}
+
+// Whether the directory a socket is in belongs to the user
+pub fn owned_by(path: &Path, uid: u32) -> bool {
+    path.parent()
+        .and_then(|dir| fs::symlink_metadata(dir).ok())
+        .is_some_and(|meta| meta.uid() == uid)
+}
+
+// The daemon runs outside of the user session and cannot see its
+// environment, so look for the compositor's socket in the user's runtime
+// dir. Only the session helper can see into it.
+pub fn discover_socket(source: FocusSource, uid: u32) -> Option<PathBuf> {
+    let runtime_dir = PathBuf::from(format!("/run/user/{}", uid));
+    let candidates: Vec<PathBuf> = match source {
+        FocusSource::Sway => list_dir(&runtime_dir)
+            .into_iter()
+            .filter(|p| file_name(p).starts_with("sway-ipc.") && file_name(p).ends_with(".sock"))
+            .collect(),
+        FocusSource::I3 => list_dir(&runtime_dir.join("i3"))
+            .into_iter()
+            .filter(|p| file_name(p).starts_with("ipc-socket."))
+            .collect(),
+        FocusSource::Hyprland => [runtime_dir.join("hypr"), PathBuf::from("/tmp/hypr")]
+            .iter()
+            .flat_map(|dir| list_dir(dir))
+            .map(|instance| instance.join(".socket2.sock"))
+            .filter(|p| p.exists())
+            .collect(),
+    };
+    // /tmp/hypr is shared with the instances of other users
+    newest(candidates.into_iter().filter(|p| owned_by(p, uid)).collect())
+}
+
+fn ipc_message(kind: u32, payload: &str) -> Vec<u8> {
+    let mut message = IPC_MAGIC.to_vec();
+    message.extend_from_slice(&(payload.len() as u32).to_ne_bytes());
+    message.extend_from_slice(&kind.to_ne_bytes());
+    message.extend_from_slice(payload.as_bytes());
+    message
+}
+
+fn app_from_node(node: &Value) -> FocusedApp {
+    FocusedApp {
+        app_id: node["app_id"].as_str().unwrap_or("").to_string(),
+        class: node["window_properties"]["class"].as_str().unwrap_or("").to_string(),
+    }
+}
+
+fn find_focused(node: &Value) -> Option<&Value> {
+    if node["focused"].as_bool() == Some(true) {
+        return Some(node);
+    }
+    ["nodes", "floating_nodes"]
+        .iter()
+        .filter_map(|key| node[*key].as_array())
+        .flatten()
+        .find_map(find_focused)
+}
+
+// Follows the focused window through the compositor's IPC socket
+pub struct FocusWatcher {
+    source: FocusSource,
+    socket: Option<PathBuf>,
+    stream: Option<UnixStream>,
+    buffer: LineReader,
+    reconnect: Reconnect,
+    focused: Option<FocusedApp>,
+}
+
+impl FocusWatcher {
+    pub fn new(source: FocusSource, socket: &str) -> FocusWatcher {
+        FocusWatcher {
+            source,
+            socket: (!socket.is_empty()).then(|| PathBuf::from(socket)),
+            stream: None,
+            buffer: LineReader::new(),
+            reconnect: Reconnect::new(),
+            focused: None,
+        }
+    }
+    fn connect(&mut self) -> Result<()> {
+        let mut stream = match &self.socket {
+            Some(path) => UnixStream::connect(path)?,
+            None => session::connect(Target::Compositor(self.source))?,
+        };
+        if self.source != FocusSource::Hyprland {
+            stream.write_all(&ipc_message(IPC_SUBSCRIBE, "[\"window\"]"))?;
+            // the tree tells which window already has focus
+            stream.write_all(&ipc_message(IPC_GET_TREE, ""))?;
+        }
+        stream.set_nonblocking(true)?;
+        self.buffer.clear();
+        self.stream = Some(stream);
+        Ok(())
+    }
+    fn parse_ipc(&mut self) -> bool {
+        let mut changed = false;
+        while self.buffer.buffered().len() >= IPC_HEADER_LEN {
+            let header = self.buffer.buffered();
+            if !header.starts_with(IPC_MAGIC) {
+                eprintln!("Invalid message from compositor IPC socket");
+                self.buffer.clear();
+                self.stream = None;
+                self.reconnect.disconnected();
+                break;
+            }
+            let len = u32::from_ne_bytes(header[6..10].try_into().unwrap()) as usize;
+            let kind = u32::from_ne_bytes(header[10..14].try_into().unwrap());
+            if header.len() < IPC_HEADER_LEN + len {
+                break;
+            }
+            let message = self.buffer.consume(IPC_HEADER_LEN + len);
+            let Ok(payload) = serde_json::from_slice::<Value>(&message[IPC_HEADER_LEN..]) else {
+                continue;
+            };
+            let app = match kind {
+                IPC_GET_TREE => find_focused(&payload).map(app_from_node),
+                IPC_EVENT_WINDOW if payload["change"] == "focus" => Some(app_from_node(&payload["container"])),
+                _ => None,
+            };
+            changed |= self.set_focused(app);
+        }
+        changed
+    }
+    fn parse_hyprland(&mut self) -> bool {
+        let mut changed = false;
+        while let Some(line) = self.buffer.next_line() {
+            // activewindow>>class,title
+            let Some(window) = line.trim_end().strip_prefix("activewindow>>") else {
+                continue;
+            };
+            let class = window.split(',').next().unwrap_or("").to_string();
+            changed |= self.set_focused(Some(FocusedApp {
+                app_id: class.clone(),
+                class,
+            }));
+        }
+        changed
+    }
+    // Returns true if the focused app changed
+    fn set_focused(&mut self, app: Option<FocusedApp>) -> bool {
+        if app.is_none() || app == self.focused {
+            return false;
+        }
+        self.focused = app;
+        true
+    }
+    pub fn focused(&self) -> Option<&FocusedApp> {
+        self.focused.as_ref()
+    }
+    // Connects when due and consumes pending events, returns true if the focus changed
+    pub fn update(&mut self) -> bool {
+        if self.stream.is_none() && self.reconnect.due() {
+            let connected = self.connect();
+            self.reconnect.attempted(connected, "Failed to connect to compositor for focus tracking");
+        }
+        let mut eof = false;
+        if let Some(stream) = &mut self.stream {
+            eof = !self.buffer.read_from(stream).unwrap_or_else(|e| {
+                eprintln!("Failed to read from compositor socket: {}", e);
+                false
+            });
+        }
+        let changed = match self.source {
+            FocusSource::Hyprland => self.parse_hyprland(),
+            _ => self.parse_ipc(),
+        };
+        if eof && self.stream.take().is_some() {
+            self.reconnect.disconnected();
+        }
+        changed
+    }
+    pub fn next_update(&self) -> Option<Instant> {
+        match self.stream {
+            Some(_) => None,
+            None => Some(self.reconnect.next_attempt()),
+        }
+    }
+    pub fn poll_fd(&self) -> Option<RawFd> {
+        self.stream.as_ref().map(|stream| stream.as_raw_fd())
+    }
+}
@@ -0,0 +1,145 @@
+use std::collections::HashMap;
+
+// touches starting this close to either end of the bar may swipe
+const EDGE_WIDTH: f64 = 48.0;
+// horizontal travel needed to recognize a swipe
+const SWIPE_DISTANCE: f64 = 160.0;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Swipe {
+    Next,
+    Previous,
+}
+
+struct SwipeTouch {
+    start_x: f64,
+    x: f64,
+    // -1.0 for the left edge, 1.0 for the right edge
+    edge: Option<f64>,
+}
+
+// Recognizes swipes that either start at one of the edges of the bar
+// or are made with more than one finger.
+pub struct SwipeRecognizer {
+    touches: HashMap<u32, SwipeTouch>,
+    // only one swipe per gesture, until all fingers are lifted
+    fired: bool,
+}
+
+impl SwipeRecognizer {
+    pub fn new() -> SwipeRecognizer {
+        SwipeRecognizer {
+            touches: HashMap::new(),
+            fired: false,
+        }
+    }
+    // Returns true if the touch may turn into a swipe, in which case
+    // presses should be deferred
+    pub fn down(&mut self, slot: u32, x: f64, width: f64) -> bool {
+        let edge = if x < EDGE_WIDTH {
+            Some(-1.0)
+        } else if x > width - EDGE_WIDTH {
+            Some(1.0)
+        } else {
+            None
+        };
+        self.touches.insert(slot, SwipeTouch { start_x: x, x, edge });
+        edge.is_some() || self.touches.len() > 1
+    }
+    pub fn is_edge_touch(&self, slot: u32) -> bool {
+        self.touches.get(&slot).is_some_and(|t| t.edge.is_some())
+    }
+    pub fn motion(&mut self, slot: u32, x: f64) -> Option<Swipe> {
+        let touch = self.touches.get_mut(&slot)?;
+        touch.x = x;
+        if self.fired {
+            return None;
+        }
+        let dx = if self.touches.len() > 1 {
+            self.touches.values().map(|t| t.x - t.start_x).sum::<f64>() / self.touches.len() as f64
+        } else {
+            let touch = &self.touches[&slot];
+            // edge swipes have to move away from the edge they started at
+            match touch.edge {
+                Some(edge) if (touch.x - touch.start_x) * edge < 0.0 => touch.x - touch.start_x,
+                _ => return None,
+            }
+        };
+        if dx.abs() < SWIPE_DISTANCE {
+            return None;
+        }
+        self.fired = true;
+        // moving the fingers left brings in the layer to the right
+        Some(if dx < 0.0 { Swipe::Next } else { Swipe::Previous })
+    }
+    pub fn up(&mut self, slot: u32) {
+        self.touches.remove(&slot);
+        if self.touches.is_empty() {
+            self.fired = false;
+        }
+    }
+    pub fn reset(&mut self) {
+        self.touches.clear();
+        self.fired = false;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WIDTH: f64 = 2008.0;
+
+    #[test]
+    fn edges() {
+        let mut swipes = SwipeRecognizer::new();
+        assert!(swipes.down(0, 10.0, WIDTH));
+        assert!(swipes.is_edge_touch(0));
+        assert!(swipes.down(1, WIDTH - 10.0, WIDTH));
+        assert!(swipes.is_edge_touch(1));
+        swipes.reset();
+        assert!(!swipes.down(0, WIDTH / 2.0, WIDTH));
+        assert!(!swipes.is_edge_touch(0));
+        assert!(!swipes.is_edge_touch(1));
+    }
+
+    #[test]
+    fn edge_swipes() {
+        let mut swipes = SwipeRecognizer::new();
+        swipes.down(0, 10.0, WIDTH);
+        assert_eq!(swipes.motion(0, 10.0 + SWIPE_DISTANCE / 2.0), None);
+        assert_eq!(swipes.motion(0, 10.0 + SWIPE_DISTANCE), Some(Swipe::Previous));
+        // only once per gesture
+        assert_eq!(swipes.motion(0, 10.0 + SWIPE_DISTANCE * 2.0), None);
+        swipes.up(0);
+        swipes.down(0, WIDTH - 10.0, WIDTH);
+        assert_eq!(swipes.motion(0, WIDTH - 10.0 - SWIPE_DISTANCE), Some(Swipe::Next));
+    }
+
+    #[test]
+    fn edge_swipes_move_inwards() {
+        let mut swipes = SwipeRecognizer::new();
+        swipes.down(0, WIDTH - 10.0, WIDTH);
+        assert_eq!(swipes.motion(0, WIDTH + SWIPE_DISTANCE), None);
+        swipes.reset();
+        swipes.down(0, WIDTH / 2.0, WIDTH);
+        assert_eq!(swipes.motion(0, WIDTH / 2.0 + SWIPE_DISTANCE * 2.0), None);
+    }
+
+    #[test]
+    fn two_finger_swipes() {
+        let mut swipes = SwipeRecognizer::new();
+        swipes.down(0, 500.0, WIDTH);
+        assert!(swipes.down(1, 600.0, WIDTH));
+        // the average travel counts, not the one of a single finger
+        assert_eq!(swipes.motion(0, 500.0 - SWIPE_DISTANCE), None);
+        assert_eq!(swipes.motion(1, 600.0 - SWIPE_DISTANCE), Some(Swipe::Next));
+        swipes.up(0);
+        assert_eq!(swipes.motion(1, 0.0), None);
+        swipes.up(1);
+        swipes.down(0, 500.0, WIDTH);
+        swipes.down(1, 600.0, WIDTH);
+        swipes.motion(0, 500.0 + SWIPE_DISTANCE);
+        assert_eq!(swipes.motion(1, 600.0 + SWIPE_DISTANCE), Some(Swipe::Previous));
+    }
+}
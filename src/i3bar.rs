@@ -0,0 +1,316 @@
+use std::{
+    io::{ErrorKind, Write},
+    os::fd::{AsRawFd, RawFd},
+    process::{Child, ChildStdin, ChildStdout, Command, Stdio},
+    time::{Duration, Instant},
+};
+use anyhow::Result;
+use nix::fcntl::{fcntl, FcntlArg, OFlag};
+use serde::{Deserialize, Serialize};
+use crate::{lines::LineReader, parse_color, Color};
+
+const RESTART_DELAY: Duration = Duration::from_secs(5);
+// how often click events the pipe had no room for are retried
+const CLICK_RETRY: Duration = Duration::from_millis(50);
+// click events are dropped while this much is still waiting to be written
+const MAX_PENDING_CLICKS: usize = 16 * 1024;
+
+#[derive(Deserialize)]
+struct Header {
+    version: u32,
+    #[serde(default)]
+    click_events: bool,
+}
+
+#[derive(Deserialize)]
+struct RawBlock {
+    full_text: String,
+    short_text: Option<String>,
+    color: Option<String>,
+    name: Option<String>,
+    instance: Option<String>,
+}
+
+#[derive(Serialize)]
+struct ClickEvent<'a> {
+    name: Option<&'a str>,
+    instance: Option<&'a str>,
+    button: u32,
+    modifiers: [&'a str; 0],
+    x: i32,
+    y: i32,
+}
+
+pub struct Block {
+    pub text: String,
+    pub color: Option<Color>,
+    name: Option<String>,
+    instance: Option<String>,
+}
+
+pub struct I3bar {
+    command: String,
+    child: Option<Child>,
+    stdin: Option<ChildStdin>,
+    stdout: Option<ChildStdout>,
+    buffer: LineReader,
+    header: Option<Header>,
+    // click events not yet taken by the generator, always whole
+    // events so the array stays valid JSON
+    outgoing: Vec<u8>,
+    // only report the first click dropped while the generator is stuck
+    dropping_clicks: bool,
+    sent_clicks: bool,
+    next_start: Instant,
+    blocks: Vec<Block>,
+}
+
+impl I3bar {
+    pub fn new(command: &str) -> I3bar {
+        I3bar {
+            command: command.to_string(),
+            child: None,
+            stdin: None,
+            stdout: None,
+            buffer: LineReader::new(),
+            header: None,
+            outgoing: Vec::new(),
+            dropping_clicks: false,
+            sent_clicks: false,
+            next_start: Instant::now(),
+            blocks: Vec::new(),
+        }
+    }
+    fn spawn(&mut self) -> Result<()> {
+        let mut child = Command::new("/bin/sh")
+            .arg("-c")
+            .arg(&self.command)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()?;
+        let stdout = child.stdout.take().unwrap();
+        let stdin = child.stdin.take().unwrap();
+        // never let a generator that ignores click events stall the daemon
+        fcntl(stdout.as_raw_fd(), FcntlArg::F_SETFL(OFlag::O_NONBLOCK))?;
+        fcntl(stdin.as_raw_fd(), FcntlArg::F_SETFL(OFlag::O_NONBLOCK))?;
+        self.stdin = Some(stdin);
+        self.stdout = Some(stdout);
+        self.child = Some(child);
+        self.buffer.clear();
+        self.header = None;
+        self.outgoing.clear();
+        self.sent_clicks = false;
+        Ok(())
+    }
+    fn stop(&mut self) {
+        self.stdin = None;
+        self.stdout = None;
+        if let Some(mut child) = self.child.take() {
+            let _ = child.kill();
+            let _ = child.wait();
+        }
+        self.next_start = Instant::now() + RESTART_DELAY;
+    }
+    // Parses one line of the protocol, returns true if it carried a new status line
+    fn parse_line(&mut self, line: &str) -> bool {
+        let line = line.trim();
+        if self.header.is_none() {
+            match serde_json::from_str::<Header>(line) {
+                Ok(header) if header.version >= 1 => self.header = Some(header),
+                _ => eprintln!("Invalid i3bar protocol header from \"{}\"", self.command),
+            }
+            return false;
+        }
+        // status lines are elements of an infinite array, strip the
+        // opening bracket of the array and the separating commas, which
+        // i3status leads and i3status-rs and i3blocks trail the line with
+        let line = line.strip_prefix('[').filter(|l| l.trim_start().starts_with('[')).unwrap_or(line);
+        let line = line.trim_start().trim_start_matches(',').trim_end().trim_end_matches(',').trim();
+        if line.is_empty() || line == "[" {
+            return false;
+        }
+        match serde_json::from_str::<Vec<RawBlock>>(line) {
+            Ok(blocks) => {
+                self.blocks = blocks
+                    .into_iter()
+                    .filter(|b| !b.full_text.is_empty())
+                    .map(|b| Block {
+                        text: b.short_text.filter(|t| !t.is_empty()).unwrap_or(b.full_text),
+                        color: b.color.as_deref().and_then(parse_color),
+                        name: b.name,
+                        instance: b.instance,
+                    })
+                    .collect();
+                true
+            }
+            Err(e) => {
+                eprintln!("Failed to parse i3bar status line: {}", e);
+                false
+            }
+        }
+    }
+    // Writes as much of the pending click events as the pipe takes
+    fn flush(&mut self) {
+        let Some(stdin) = &mut self.stdin else {
+            self.outgoing.clear();
+            return;
+        };
+        while !self.outgoing.is_empty() {
+            match stdin.write(&self.outgoing) {
+                Ok(n) => {
+                    self.outgoing.drain(..n);
+                    self.dropping_clicks = false;
+                }
+                Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) => {
+                    eprintln!("Failed to send click event to \"{}\": {}", self.command, e);
+                    self.outgoing.clear();
+                    break;
+                }
+            }
+        }
+    }
+    // Starts the generator if needed and consumes its output, returns
+    // true if the set of blocks changed.
+    pub fn update(&mut self) -> bool {
+        self.flush();
+        if self.child.is_none() && Instant::now() >= self.next_start {
+            if let Err(e) = self.spawn() {
+                eprintln!("Failed to run \"{}\": {}", self.command, e);
+                self.next_start = Instant::now() + RESTART_DELAY;
+            }
+        }
+        let mut eof = false;
+        if let Some(stdout) = &mut self.stdout {
+            eof = !self.buffer.read_from(stdout).unwrap_or_else(|e| {
+                eprintln!("Failed to read output of \"{}\": {}", self.command, e);
+                false
+            });
+        }
+        let mut changed = false;
+        while let Some(line) = self.buffer.next_line() {
+            changed |= self.parse_line(&line);
+        }
+        if eof {
+            eprintln!("i3bar command \"{}\" exited, restarting", self.command);
+            self.stop();
+        }
+        changed
+    }
+    pub fn blocks(&self) -> &[Block] {
+        &self.blocks
+    }
+    pub fn click(&mut self, block: usize, x: f64, y: f64) {
+        if !self.header.as_ref().is_some_and(|h| h.click_events) {
+            return;
+        }
+        let (Some(block), Some(_)) = (self.blocks.get(block), &self.stdin) else {
+            return;
+        };
+        if self.outgoing.len() > MAX_PENDING_CLICKS {
+            if !self.dropping_clicks {
+                eprintln!("\"{}\" is not reading click events, dropping them", self.command);
+                self.dropping_clicks = true;
+            }
+            return;
+        }
+        let event = ClickEvent {
+            name: block.name.as_deref(),
+            instance: block.instance.as_deref(),
+            button: 1,
+            modifiers: [],
+            x: x as i32,
+            y: y as i32,
+        };
+        // click events are sent as an infinite array as well. Queued
+        // events are written whole and in order, so the array is open
+        // once the first one is queued.
+        let prefix = if self.sent_clicks { "," } else { "[\n" };
+        let message = format!("{}{}\n", prefix, serde_json::to_string(&event).unwrap());
+        self.outgoing.extend_from_slice(message.as_bytes());
+        self.sent_clicks = true;
+        self.flush();
+    }
+    pub fn next_update(&self) -> Option<Instant> {
+        match self.child {
+            // the pipe gives no notice once it has room again
+            Some(_) if !self.outgoing.is_empty() => Some(Instant::now() + CLICK_RETRY),
+            Some(_) => None,
+            None => Some(self.next_start),
+        }
+    }
+    pub fn poll_fd(&self) -> Option<RawFd> {
+        self.stdout.as_ref().map(|stdout| stdout.as_raw_fd())
+    }
+}
+
+impl Drop for I3bar {
+    fn drop(&mut self) {
+        if let Some(child) = &mut self.child {
+            let _ = child.kill();
+            let _ = child.wait();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn feed(bar: &mut I3bar, output: &str) -> usize {
+        output.lines().filter(|line| bar.parse_line(line)).count()
+    }
+
+    #[test]
+    fn parses_i3status_rs_output() {
+        let mut bar = I3bar::new("i3status-rs");
+        let output = concat!(
+            "{\"version\": 1, \"click_events\": true}\n",
+            "[\n",
+            "[{\"full_text\":\" CPU  12% \",\"name\":\"cpu\",\"instance\":\"0:\",\"color\":\"#a3be8c\",\"separator\":false,\"separator_block_width\":0,\"markup\":\"pango\"},",
+            "{\"full_text\":\"\",\"name\":\"sep\"},",
+            "{\"full_text\":\" 21:30 \",\"short_text\":\"21:30\",\"name\":\"time\",\"instance\":\"1:\"}],\n",
+            "[{\"full_text\":\" CPU  40% \",\"name\":\"cpu\",\"instance\":\"0:\"},",
+            "{\"full_text\":\" 21:31 \",\"name\":\"time\",\"instance\":\"1:\"}],\n",
+        );
+        assert_eq!(feed(&mut bar, output), 2);
+        assert!(bar.header.as_ref().unwrap().click_events);
+        let blocks = bar.blocks();
+        assert_eq!(blocks.len(), 2);
+        assert_eq!(blocks[0].text, " CPU  40% ");
+        assert_eq!(blocks[0].name.as_deref(), Some("cpu"));
+        assert_eq!(blocks[1].instance.as_deref(), Some("1:"));
+    }
+
+    #[test]
+    fn parses_i3blocks_output() {
+        let mut bar = I3bar::new("i3blocks");
+        let output = concat!(
+            "{\"version\":1,\"click_events\":true}\n",
+            "[\n",
+            "[{\"name\":\"volume\",\"full_text\":\"VOL 35%\",\"color\":\"#FFFFFF\"},",
+            "{\"name\":\"battery\",\"instance\":\"BAT0\",\"full_text\":\"BAT 87%\",\"short_text\":\"87%\"}],\n",
+        );
+        assert_eq!(feed(&mut bar, output), 1);
+        let blocks = bar.blocks();
+        assert_eq!(blocks.len(), 2);
+        assert_eq!(blocks[0].text, "VOL 35%");
+        assert!(blocks[0].color.is_some());
+        assert_eq!(blocks[1].text, "87%");
+    }
+
+    #[test]
+    fn parses_i3status_output() {
+        let mut bar = I3bar::new("i3status");
+        let output = concat!(
+            "{\"version\":1}\n",
+            "[\n",
+            "[{\"name\":\"load\",\"markup\":\"none\",\"full_text\":\"0.52\"}]\n",
+            ",[{\"name\":\"load\",\"markup\":\"none\",\"full_text\":\"0.61\"}]\n",
+        );
+        assert_eq!(feed(&mut bar, output), 2);
+        assert!(!bar.header.as_ref().unwrap().click_events);
+        assert_eq!(bar.blocks()[0].text, "0.61");
+    }
+}
@@ -0,0 +1,78 @@
+use std::{
+    fs,
+    path::PathBuf,
+    time::{Duration, Instant},
+};
+
+const LEDS_DIR: &str = "/sys/class/leds";
+// the LED class has no change notifications, so the brightness is polled
+const POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+// Follows LEDs in /sys/class/leds. A function like "capslock" matches the
+// LED of every keyboard, a full name like "platform::micmute" only that one.
+pub struct LedState {
+    name: String,
+    lit: bool,
+    next_check: Instant,
+    // only LEDs of buttons that can be seen are polled
+    watched: bool,
+}
+
+impl LedState {
+    pub fn new(name: &str) -> LedState {
+        let mut led = LedState {
+            name: name.to_string(),
+            lit: false,
+            next_check: Instant::now(),
+            watched: true,
+        };
+        led.update();
+        led
+    }
+    // keyboards come and go, so look the LEDs up on every check
+    fn paths(&self) -> Vec<PathBuf> {
+        let suffix = format!("::{}", self.name);
+        let full_name = self.name.contains("::");
+        fs::read_dir(LEDS_DIR)
+            .map(|entries| entries.filter_map(|e| e.ok()).collect::<Vec<_>>())
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|entry| {
+                let file_name = entry.file_name();
+                let file_name = file_name.to_string_lossy();
+                match full_name {
+                    true => file_name == self.name,
+                    false => file_name.ends_with(&suffix),
+                }
+            })
+            .map(|entry| entry.path().join("brightness"))
+            .collect()
+    }
+    // Polling stops while the button is on a hidden layer or the bar is off,
+    // and picks up with a check as soon as it is shown again
+    pub fn watch(&mut self, watched: bool) {
+        if watched && !self.watched {
+            self.next_check = Instant::now();
+        }
+        self.watched = watched;
+    }
+    // Returns true if the LED was switched on or off since the last check
+    pub fn update(&mut self) -> bool {
+        if !self.watched || Instant::now() < self.next_check {
+            return false;
+        }
+        self.next_check = Instant::now() + POLL_INTERVAL;
+        let lit = self.paths().iter().any(|path| {
+            fs::read_to_string(path).is_ok_and(|value| value.trim().parse::<u32>().is_ok_and(|v| v > 0))
+        });
+        let changed = lit != self.lit;
+        self.lit = lit;
+        changed
+    }
+    pub fn lit(&self) -> bool {
+        self.lit
+    }
+    pub fn next_update(&self) -> Option<Instant> {
+        self.watched.then_some(self.next_check)
+    }
+}
@@ -0,0 +1,2 @@
+// Parts shared by the daemon, tiny-dfr-ctl and the tests
+pub mod protocol;
@@ -0,0 +1,92 @@
+use std::io::{self, ErrorKind, Read};
+
+// Collects what a nonblocking pipe or socket has to offer until whole
+// lines have arrived
+pub struct LineReader {
+    buffer: Vec<u8>,
+}
+
+impl LineReader {
+    pub fn new() -> LineReader {
+        LineReader { buffer: Vec::new() }
+    }
+    // Reads everything available without blocking, returns false once the
+    // other end is closed
+    pub fn read_from(&mut self, source: &mut impl Read) -> io::Result<bool> {
+        let mut chunk = [0u8; 4096];
+        loop {
+            match source.read(&mut chunk) {
+                Ok(0) => return Ok(false),
+                Ok(n) => self.buffer.extend_from_slice(&chunk[..n]),
+                Err(e) if e.kind() == ErrorKind::WouldBlock => return Ok(true),
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            }
+        }
+    }
+    // Takes the next complete line, without its newline
+    pub fn next_line(&mut self) -> Option<String> {
+        let end = self.buffer.iter().position(|&b| b == b'\n')?;
+        let line = self.buffer.drain(..=end).collect::<Vec<_>>();
+        Some(String::from_utf8_lossy(&line[..end]).into_owned())
+    }
+    // Treats a trailing unterminated line as complete, for once the other end closed
+    pub fn finish(&mut self) {
+        if !self.buffer.is_empty() && !self.buffer.ends_with(b"\n") {
+            self.buffer.push(b'\n');
+        }
+    }
+    // Data that is not split into lines is taken from the buffer directly
+    pub fn buffered(&self) -> &[u8] {
+        &self.buffer
+    }
+    pub fn consume(&mut self, len: usize) -> Vec<u8> {
+        self.buffer.drain(..len).collect()
+    }
+    pub fn clear(&mut self) {
+        self.buffer.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn splits_lines() {
+        let mut reader = LineReader::new();
+        assert!(!reader.read_from(&mut &b"one\ntwo\r\nthr"[..]).unwrap());
+        assert_eq!(reader.next_line().as_deref(), Some("one"));
+        assert_eq!(reader.next_line().as_deref(), Some("two\r"));
+        assert_eq!(reader.next_line(), None);
+        assert!(!reader.read_from(&mut &b"ee\n"[..]).unwrap());
+        assert_eq!(reader.next_line().as_deref(), Some("three"));
+        assert!(reader.buffered().is_empty());
+    }
+
+    #[test]
+    fn finishes_the_last_line() {
+        let mut reader = LineReader::new();
+        reader.read_from(&mut &b"done"[..]).unwrap();
+        assert_eq!(reader.next_line(), None);
+        reader.finish();
+        assert_eq!(reader.next_line().as_deref(), Some("done"));
+        reader.finish();
+        assert_eq!(reader.next_line(), None);
+    }
+
+    #[test]
+    fn stops_when_nothing_is_available() {
+        let (mut ours, mut theirs) = std::os::unix::net::UnixStream::pair().unwrap();
+        ours.set_nonblocking(true).unwrap();
+        io::Write::write_all(&mut theirs, b"partial").unwrap();
+        let mut reader = LineReader::new();
+        assert!(reader.read_from(&mut ours).unwrap());
+        assert_eq!(reader.buffered(), b"partial");
+        drop(theirs);
+        assert!(!reader.read_from(&mut ours).unwrap());
+        assert_eq!(reader.consume(4), b"part");
+        reader.clear();
+        assert!(reader.buffered().is_empty());
+    }
+}
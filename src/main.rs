@@ -1,4 +1,4 @@
-use anyhow::Result;
+use anyhow::{Result, anyhow};
 use cairo::{Context, FontSlant, FontWeight, Format, ImageSurface, Rectangle};
 use chrono::Local;
 use drm::control::ClipRect;
@@ -16,11 +16,12 @@ use input::{
     },
     Device as InputDevice, Libinput, LibinputInterface,
 };
-use input_linux::{uinput::UInputHandle, EventKind, Key, SynchronizeKind};
+use input_linux::{uinput::UInputHandle, EventKind, Key, LedKind, SynchronizeKind};
 use input_linux_sys::{input_event, input_id, timeval, uinput_setup};
 use libc::{c_char, O_ACCMODE, O_RDONLY, O_RDWR, O_WRONLY};
 use nix::poll::{poll, PollFd, PollFlags};
 use privdrop::PrivDrop;
+use regex::Regex;
 use rsvg::{CairoRenderer, Loader, SvgHandle};
 use serde::Deserialize;
 use lazy_static::lazy_static;
@@ -28,178 +29,419 @@ use std::{
     collections::HashMap,
     fs::{read_to_string, self, File, OpenOptions},
     os::{
-        fd::AsRawFd,
+        fd::{AsRawFd, RawFd},
         unix::{fs::OpenOptionsExt, io::OwnedFd},
     },
     path::{Path, PathBuf},
-    time::{SystemTime},
+    time::{Duration, Instant, SystemTime},
 };
 
+mod als;
 mod backlight;
+#[cfg(feature = "dbus")]
+mod art;
+mod command;
+mod control;
+#[cfg(feature = "dbus")]
+mod dbus_service;
 mod display;
-
-use backlight::BacklightManager;
+mod fnlock;
+mod focus;
+mod gesture;
+mod i3bar;
+mod led;
+mod lines;
+mod modifiers;
+#[cfg(feature = "dbus")]
+mod mpris;
+mod navigation;
+#[cfg(feature = "dbus")]
+mod notifications;
+#[cfg(feature = "dbus")]
+mod property;
+mod reconnect;
+mod scroll;
+mod session;
+mod slider;
+mod status;
+mod sysload;
+#[cfg(all(test, feature = "dbus"))]
+mod test_bus;
+mod transition;
+
+#[cfg(feature = "dbus")]
+use art::ArtCache;
+use als::AmbientLight;
+use backlight::{BacklightManager, DisplayBacklight};
+use command::CommandOutput;
+use control::ControlServer;
+#[cfg(feature = "dbus")]
+use dbus_service::DbusService;
 use display::DrmBackend;
+use fnlock::FnLock;
+use focus::{FocusedApp, FocusRule, FocusSource, FocusWatcher};
+use gesture::{Swipe, SwipeRecognizer};
+use i3bar::I3bar;
+use led::LedState;
+use modifiers::Modifiers;
+#[cfg(feature = "dbus")]
+use mpris::{MprisWatcher, NowPlaying};
+use navigation::LayerStack;
+#[cfg(feature = "dbus")]
+use notifications::{NotificationEvent, NotificationWatcher};
+#[cfg(feature = "dbus")]
+use property::PropertyWatcher;
+use tiny_dfr::protocol::{self, ButtonStatus, Request, Response, Status};
+use scroll::Scroll;
+use slider::{Slider, SliderTarget};
+use status::{StatusSource, Toggle};
+use sysload::{SysLoad, SysLoadKind};
+use transition::Transition;
 
 const BUTTON_COLOR_INACTIVE: f64 = 0.200;
 const BUTTON_COLOR_ACTIVE: f64 = 0.400;
 const TIMEOUT_MS: i32 = 30 * 1000;
+// messages too wide for the bar scroll at this many pixels per second,
+// after resting at the start for a moment
+const MARQUEE_SPEED: f64 = 120.0;
+const MARQUEE_DELAY: Duration = Duration::from_secs(1);
+const MARQUEE_FRAME_TIME: Duration = Duration::from_millis(16);
+// movement after which a touch on a scrollable layer scrolls instead of pressing
+const SCROLL_THRESHOLD: f64 = 16.0;
+// how long a still touch on a scrollable layer waits before pressing the button
+const PRESS_DELAY: Duration = Duration::from_millis(120);
 const CONFIG_PATH: &str = "/etc/tiny-dfr.conf";
+// popover layers are stored after the five configured layers
+const CONFIGURED_LAYERS: usize = 5;
+
+type Color = (f64, f64, f64);
+
+// Parses "#rrggbb" style colors
+fn parse_color(color: &str) -> Option<Color> {
+    let hex = color.strip_prefix('#')?;
+    if hex.len() != 6 {
+        return None;
+    }
+    let value = u32::from_str_radix(hex, 16).ok()?;
+    Some((
+        ((value >> 16) & 0xff) as f64 / 255.0,
+        ((value >> 8) & 0xff) as f64 / 255.0,
+        (value & 0xff) as f64 / 255.0,
+    ))
+}
 
 enum ButtonImage {
     Text(String),
     Svg(SvgHandle),
     Png(DynamicImage),
     Time(u16),
+    SysLoad(SysLoad),
     Blank,
 }
 
+// Actions handled by the daemon itself instead of emitting a key
+#[derive(Clone, Copy, PartialEq)]
+enum LayerAction {
+    // switch to the popover layer with the given index
+    Open(usize),
+    // return to the previously active layer
+    Back,
+}
+
+// Media player buttons, driven over MPRIS instead of media keys
+#[derive(Clone, Copy, PartialEq)]
+enum MediaControl {
+    // title and artist of the current track, tapping toggles playback
+    NowPlaying,
+    PlayPause,
+    Next,
+    Previous,
+}
+
+impl MediaControl {
+    #[cfg_attr(not(feature = "dbus"), allow(dead_code))]
+    fn method(self) -> &'static str {
+        match self {
+            MediaControl::NowPlaying | MediaControl::PlayPause => "PlayPause",
+            MediaControl::Next => "Next",
+            MediaControl::Previous => "Previous",
+        }
+    }
+}
+
+#[cfg_attr(not(feature = "dbus"), allow(dead_code))]
+struct MediaButton {
+    control: MediaControl,
+    // the play/pause icon is swapped with the playback status
+    icon_theme: String,
+    // album art shown next to the now playing text, already scaled to fit
+    art: Option<ImageSurface>,
+}
+
+// Content pushed over the control socket that reverts once it expires,
+// fields that are None show the configured content
+struct PushedContent {
+    image: Option<ButtonImage>,
+    color: Option<Option<Color>>,
+    badge: Option<Option<String>>,
+    until: Instant,
+}
+
 struct Button {
     image: ButtonImage,
+    color: Option<Color>,
+    // shown in the top right corner, e.g. a count
+    badge: Option<String>,
+    pushed: Option<PushedContent>,
+    // identifies the button for updates over the control socket
+    name: String,
+    // presses (true) and releases not yet reported to subscribers
+    transitions: Vec<bool>,
+    command: Option<CommandOutput>,
+    slider: Option<Slider>,
+    layer_action: Option<LayerAction>,
+    media: Option<MediaButton>,
+    // lock keys show whether their keyboard LED is lit
+    led: Option<LedState>,
+    // toggles like mic mute show their state with the label and color
+    toggle: Option<Toggle>,
     changed: bool,
     active: bool,
     action: Key,
 }
 
-impl Button {
-    fn new_text(text: &str, action: Key) -> Button {
-        Button {
-            action,
-            active: false,
-            changed: false,
-            image: ButtonImage::Text(text.to_string()),
-        }
-    }
-    fn new_icon(icon_name: &str, action: Key, icon_theme: &str) -> Button {
-        let mut search_paths: Vec<PathBuf> = vec![
-            PathBuf::from("/usr/share/tiny-dfr/icons/"),
-            PathBuf::from("/usr/share/icons/"),
-        ];
-        let mut loader = IconLoader::new();
-        search_paths.extend(loader.search_paths().into_owned());
-        loader.set_search_paths(search_paths);
-        loader.set_theme_name_provider(icon_theme);
-        loader.update_theme_name().unwrap();
-        let image;
-        match loader.load_icon(icon_name) {
-            Some(icon_loader) => {
-                let icon = icon_loader.file_for_size(512);
-                match icon.icon_type() {
-                    IconFileType::SVG => {
-                        image = ButtonImage::Svg(Loader::new().read_path(icon.path()).unwrap());
-                    }
-                    IconFileType::PNG => {
-                        image = ButtonImage::Png(image::open(icon.path()).unwrap());
-                    }
-                    IconFileType::XPM => {
-                        panic!("Legacy XPM icons are not supported")
-                    }
+// Icon names also come from commands and clients at runtime, so a missing
+// or broken icon must not take the daemon down
+fn try_load_icon(icon_name: &str, icon_theme: &str) -> Result<ButtonImage> {
+    let mut search_paths: Vec<PathBuf> = vec![
+        PathBuf::from("/usr/share/tiny-dfr/icons/"),
+        PathBuf::from("/usr/share/icons/"),
+    ];
+    let mut loader = IconLoader::new();
+    search_paths.extend(loader.search_paths().into_owned());
+    loader.set_search_paths(search_paths);
+    loader.set_theme_name_provider(icon_theme);
+    loader.update_theme_name()?;
+    let image;
+    match loader.load_icon(icon_name) {
+        Some(icon_loader) => {
+            let icon = icon_loader.file_for_size(512);
+            match icon.icon_type() {
+                IconFileType::SVG => {
+                    image = ButtonImage::Svg(Loader::new().read_path(icon.path())?);
+                }
+                IconFileType::PNG => {
+                    image = ButtonImage::Png(image::open(icon.path())?);
                 }
-            }       
-            None => {
-                // If loading the icon from the theme fails, try /usr/share/pixmaps
+                IconFileType::XPM => {
+                    return Err(anyhow!("Legacy XPM icons are not supported"));
+                }
+            }
+        }       
+        None => {
+            // If loading the icon from the theme fails, try /usr/share/pixmaps
 
-                let icon_path_svg = Path::new("/usr/share/pixmaps").join(format!("{}.svg", icon_name));
-                let icon_path_png = Path::new("/usr/share/pixmaps").join(format!("{}.png", icon_name));
+            let icon_path_svg = Path::new("/usr/share/pixmaps").join(format!("{}.svg", icon_name));
+            let icon_path_png = Path::new("/usr/share/pixmaps").join(format!("{}.png", icon_name));
 
 
-                if icon_path_svg.exists() {
-                        image = ButtonImage::Svg(Loader::new().read_path(icon_path_svg).unwrap());
-                } else if icon_path_png.exists() {
-                        image = ButtonImage::Png(image::open(icon_path_png).unwrap());
-                } else {
-                    // If the icon is not found in /usr/share/pixmaps, use the icon_name as text
-                        image = ButtonImage::Text(icon_name.to_string());
-                }
+            if icon_path_svg.exists() {
+                    image = ButtonImage::Svg(Loader::new().read_path(icon_path_svg)?);
+            } else if icon_path_png.exists() {
+                    image = ButtonImage::Png(image::open(icon_path_png)?);
+            } else {
+                // If the icon is not found in /usr/share/pixmaps, use the icon_name as text
+                    image = ButtonImage::Text(icon_name.to_string());
             }
-        };
+        }
+    };
+    Ok(image)
+}
+
+// Shows the icon name as text if the icon cannot be loaded
+fn load_icon(icon_name: &str, icon_theme: &str) -> ButtonImage {
+    try_load_icon(icon_name, icon_theme).unwrap_or_else(|e| {
+        eprintln!("Failed to load icon {}: {}", icon_name, e);
+        ButtonImage::Text(icon_name.to_string())
+    })
+}
+
+impl Button {
+    // The fields every kind of button starts out with, the constructors
+    // below only fill in what makes them different
+    fn with_content(image: ButtonImage, action: Key) -> Button {
         Button {
-            action,
-            active: false,
-            changed: false,
             image,
+            color: None,
+            badge: None,
+            pushed: None,
+            name: String::new(),
+            transitions: Vec::new(),
+            command: None,
+            slider: None,
+            layer_action: None,
+            media: None,
+            led: None,
+            toggle: None,
+            changed: false,
+            active: false,
+            action,
         }
     }
+    fn new_text(text: &str, action: Key) -> Button {
+        Button::with_content(ButtonImage::Text(text.to_string()), action)
+    }
+    fn new_icon(icon_name: &str, action: Key, icon_theme: &str) -> Button {
+        Button::with_content(load_icon(icon_name, icon_theme), action)
+    }
     fn new_time(use_24_hour: u16) -> Button {
+        Button::with_content(ButtonImage::Time(use_24_hour), Key::Time)
+    }
+    fn new_sysload(widget: SysLoad) -> Button {
+        Button::with_content(ButtonImage::SysLoad(widget), Key::Unknown)
+    }
+    fn new_command(command: CommandOutput, action: Key) -> Button {
         Button {
-            action: Key::Time,
-            active: false,
-            changed: false,
-            image: ButtonImage::Time(use_24_hour),
+            command: Some(command),
+            ..Button::with_content(ButtonImage::Text(String::new()), action)
         }
     }
-    fn new_blank() -> Button {
+    fn new_block(text: &str, color: Option<Color>) -> Button {
         Button {
-            action: Key::Unknown,
-            active: false,
-            changed: false,
-            image: ButtonImage::Blank,
+            color,
+            ..Button::new_text(text, Key::Unknown)
+        }
+    }
+    fn new_slider(icon_name: &str, icon_theme: &str, slider: Slider, getter: Option<CommandOutput>) -> Button {
+        Button {
+            command: getter,
+            slider: Some(slider),
+            ..Button::new_icon(icon_name, Key::Unknown, icon_theme)
+        }
+    }
+    fn new_layer_action(icon_name: &str, icon_theme: &str, layer_action: LayerAction) -> Button {
+        Button {
+            layer_action: Some(layer_action),
+            ..Button::new_icon(icon_name, Key::Unknown, icon_theme)
+        }
+    }
+    fn new_blank() -> Button {
+        Button::with_content(ButtonImage::Blank, Key::Unknown)
+    }
+    fn current_image(&self) -> &ButtonImage {
+        self.pushed.as_ref().and_then(|p| p.image.as_ref()).unwrap_or(&self.image)
+    }
+    fn current_color(&self) -> Option<Color> {
+        self.pushed.as_ref().and_then(|p| p.color).unwrap_or(self.color)
+    }
+    fn current_badge(&self) -> Option<&str> {
+        match self.pushed.as_ref().and_then(|p| p.badge.as_ref()) {
+            Some(badge) => badge.as_deref(),
+            None => self.badge.as_deref(),
         }
     }
+    // Replaces the given parts of the content, for ttl if set or until the next update
+    fn push_content(
+        &mut self,
+        image: Option<ButtonImage>,
+        color: Option<Option<Color>>,
+        badge: Option<Option<String>>,
+        ttl: Option<Duration>,
+    ) {
+        self.changed = true;
+        let Some(ttl) = ttl else {
+            // temporary content of the other parts keeps running until it expires
+            if let Some(pushed) = &mut self.pushed {
+                if image.is_some() {
+                    pushed.image = None;
+                }
+                if color.is_some() {
+                    pushed.color = None;
+                }
+                if badge.is_some() {
+                    pushed.badge = None;
+                }
+                if pushed.image.is_none() && pushed.color.is_none() && pushed.badge.is_none() {
+                    self.pushed = None;
+                }
+            }
+            if let Some(image) = image {
+                self.image = image;
+            }
+            if let Some(color) = color {
+                self.color = color;
+            }
+            if let Some(badge) = badge {
+                self.badge = badge;
+            }
+            return;
+        };
+        let pushed = self.pushed.get_or_insert(PushedContent {
+            image: None,
+            color: None,
+            badge: None,
+            until: Instant::now(),
+        });
+        pushed.image = image.or(pushed.image.take());
+        pushed.color = color.or(pushed.color);
+        pushed.badge = badge.or(pushed.badge.take());
+        pushed.until = Instant::now() + ttl;
+    }
     fn render(&self, c: &Context, height: f64, left_edge: f64, button_width: f64) {
-        match &self.image {
+        match self.current_image() {
             ButtonImage::Text(text) => {
-                let extents = c.text_extents(text).unwrap();
+                // album art goes on the left of the track, if the button is wide enough
+                let art = self.media.as_ref().and_then(|media| media.art.as_ref()).filter(|_| button_width > height * 2.0);
+                let (left_edge, button_width) = match art {
+                    Some(art) => {
+                        let size = art.width() as f64;
+                        let y = (height - size) / 2.0;
+                        let x = left_edge + y;
+                        c.save().unwrap();
+                        rounded_box_path(c, x, size, y, y + size, 6.0);
+                        c.clip();
+                        c.set_source_surface(art, x, y).unwrap();
+                        c.paint().unwrap();
+                        c.restore().unwrap();
+                        (x + size, button_width - size - y)
+                    }
+                    None => (left_edge, button_width),
+                };
+                // track titles are cut to the button instead of spilling over its neighbours
+                let text = match &self.media {
+                    Some(media) if media.control == MediaControl::NowPlaying => elide(c, text, button_width * 0.9),
+                    _ => text.clone(),
+                };
+                let extents = c.text_extents(&text).unwrap();
                 c.move_to(
                     left_edge + button_width / 2.0 - extents.width() / 2.0,
                     height / 2.0 + extents.height() / 2.0,
                 );
-                c.show_text(text).unwrap();
+                c.show_text(&text).unwrap();
             },
             ButtonImage::Svg(svg) => {
                 let renderer = CairoRenderer::new(&svg);
                 let y = 0.10 * height;
                 let size = height - y * 2.0;
                 let x = left_edge + button_width / 2.0 - size / 2.0;
+                if self.current_color().is_some() {
+                    // tint the icon by using it as a mask for the current source
+                    c.push_group();
+                }
                 renderer
                     .render_document(c, &Rectangle::new(x, y, size, size))
                     .unwrap();
+                if self.current_color().is_some() {
+                    let icon = c.pop_group().unwrap();
+                    c.mask(&icon).unwrap();
+                }
             },
             ButtonImage::Png(png) => {
                 let y = 0.10 * height;
                 let size = height - y * 2.0;
                 let x = left_edge + button_width / 2.0 - size / 2.0;
 
-                // Resize the PNG image to match the specified size
-                let resized_png = resize(
-                    png,
-                    size as u32,
-                    size as u32,
-                    FilterType::Lanczos3,
-                );
-
-                // Convert the resized PNG image to a Cairo ImageSurface
-                let png_surface = ImageSurface::create(
-                    Format::ARgb32,
-                    size as i32,
-                    size as i32,
-                ).expect("Failed to create PNG surface");
-
-                let png_context = Context::new(&png_surface)
-                    .expect("Failed to create PNG context");
-
-                // Iterate over the pixels of the resized PNG image and paint them on the Cairo surface
-                for (x_pixel, y_pixel, pixel) in resized_png.enumerate_pixels() {
-                    let channels = pixel.channels();
-                    let (r, g, b, a) = (channels[0], channels[1], channels[2], channels[3]);
-                    let _ = png_context.set_source_rgba(
-                        r as f64 / 255.0,
-                        g as f64 / 255.0,
-                        b as f64 / 255.0,
-                        a as f64 / 255.0,
-                    );
-                    let _ = png_context.rectangle(
-                        x_pixel as f64,
-                        y_pixel as f64,
-                        1.0,
-                        1.0,
-                    );
-                    let _ = png_context.fill();
-                }
-
-                // Composite the PNG surface onto the main context (the `c` context)
-                let _ = c.set_source_surface(&png_surface, x, y);
-                let _ = c.paint().expect("Failed to composite PNG image");
+                paint_image(c, png, x, y, size);
             },
             ButtonImage::Time(use_24_hour) => {
                 let current_time = Local::now();
@@ -246,10 +488,142 @@ impl Button {
                 );
                 c.show_text(&formatted_time).unwrap();
             },
+            ButtonImage::SysLoad(widget) => {
+                let label = widget.label();
+                if widget.graph {
+                    // draw a sparkline of the recent samples with the current value on top
+                    let bot = 0.85 * height;
+                    let top = 0.15 * height;
+                    let step = button_width / (widget.history_capacity() - 1) as f64;
+                    c.set_line_width(2.0);
+                    for (i, sample) in widget.history().enumerate() {
+                        let x = left_edge + i as f64 * step;
+                        let y = bot - sample * (bot - top);
+                        if i == 0 {
+                            c.move_to(x, y);
+                        } else {
+                            c.line_to(x, y);
+                        }
+                    }
+                    c.stroke().unwrap();
+                    c.set_font_size(20.0);
+                }
+                let extents = c.text_extents(&label).unwrap();
+                c.move_to(
+                    left_edge + button_width / 2.0 - extents.width() / 2.0,
+                    height / 2.0 + extents.height() / 2.0,
+                );
+                c.show_text(&label).unwrap();
+                c.set_font_size(32.0);
+            },
             _ => {
             }
         }
     }
+    fn render_badge(&self, c: &Context, height: f64, left_edge: f64, button_width: f64) {
+        let Some(badge) = self.current_badge().filter(|b| !b.is_empty()) else {
+            return;
+        };
+        let radius = 14.0;
+        let x = left_edge + button_width - radius;
+        let y = 0.15 * height + radius;
+        c.save().unwrap();
+        c.set_source_rgb(0.85, 0.15, 0.15);
+        c.arc(x, y, radius, 0.0, 360.0f64.to_radians());
+        c.fill().unwrap();
+        c.set_source_rgb(1.0, 1.0, 1.0);
+        c.set_font_size(18.0);
+        let extents = c.text_extents(badge).unwrap();
+        c.move_to(x - extents.width() / 2.0, y + extents.height() / 2.0);
+        c.show_text(badge).unwrap();
+        c.restore().unwrap();
+    }
+    fn update(&mut self) {
+        if self.pushed.as_ref().is_some_and(|p| Instant::now() >= p.until) {
+            self.pushed = None;
+            self.changed = true;
+        }
+        if let ButtonImage::SysLoad(widget) = &mut self.image {
+            if widget.update() {
+                self.changed = true;
+            }
+        }
+        if let Some(slider) = &mut self.slider {
+            self.changed |= slider.update();
+        }
+        if let Some(led) = &mut self.led {
+            self.changed |= led.update();
+        }
+        if let Some(toggle) = &mut self.toggle {
+            if toggle.update() {
+                self.image = match &toggle.icon_theme {
+                    Some(theme) => load_icon(toggle.label(), theme),
+                    None => ButtonImage::Text(toggle.label().to_string()),
+                };
+                self.color = toggle.color();
+                self.changed = true;
+            }
+        }
+        if let Some(command) = &mut self.command {
+            if let Some(output) = command.update() {
+                // a slider's command is its getter and only sets the level
+                if let Some(slider) = &mut self.slider {
+                    if let Some(level) = slider::parse_level(&output.text) {
+                        self.changed |= slider.set_level(level);
+                    }
+                    return;
+                }
+                self.image = match output.icon {
+                    // a bad icon falls back to the text the command printed along with it
+                    Some(icon) => try_load_icon(&icon, &command.icon_theme).unwrap_or_else(|e| {
+                        eprintln!("Failed to load icon {} of \"{}\": {}", icon, command.command(), e);
+                        match output.text.is_empty() {
+                            true => ButtonImage::Text(icon),
+                            false => ButtonImage::Text(output.text),
+                        }
+                    }),
+                    None => ButtonImage::Text(output.text),
+                };
+                self.color = output.color;
+                self.changed = true;
+            }
+        }
+    }
+    fn watch_leds(&mut self, watched: bool) {
+        if let Some(led) = &mut self.led {
+            led.watch(watched);
+        }
+        if let Some(toggle) = &mut self.toggle {
+            toggle.watch_led(watched);
+        }
+    }
+    fn next_update(&self) -> Option<Instant> {
+        let pushed_update = self.pushed.as_ref().map(|p| p.until);
+        self.content_update().into_iter().chain(pushed_update).min()
+    }
+    fn content_update(&self) -> Option<Instant> {
+        let led_update = self.led.as_ref().and_then(|led| led.next_update())
+            .into_iter()
+            .chain(self.toggle.as_ref().and_then(|toggle| toggle.next_update()))
+            .min();
+        let slider_update = self.slider.as_ref().and_then(|slider| slider.next_update()).into_iter().chain(led_update).min();
+        if let Some(command) = &self.command {
+            return command.next_update().into_iter().chain(slider_update).min();
+        }
+        if slider_update.is_some() {
+            return slider_update;
+        }
+        match &self.image {
+            ButtonImage::SysLoad(widget) => Some(widget.next_update()),
+            _ => None,
+        }
+    }
+    fn poll_fd(&self) -> Option<RawFd> {
+        self.command
+            .as_ref()
+            .and_then(|command| command.poll_fd())
+            .or_else(|| self.toggle.as_ref().and_then(|toggle| toggle.poll_fd()))
+    }
     fn set_active<F>(&mut self, uinput: &mut UInputHandle<F>, active: bool)
     where
         F: AsRawFd,
@@ -257,17 +631,261 @@ impl Button {
         if self.active != active {
             self.active = active;
             self.changed = true;
+            self.transitions.push(active);
+
+            if self.action != Key::Unknown {
+                toggle_key(uinput, self.action, active as i32);
+            }
+        }
+    }
+    // Text shown on the button, icons have none
+    fn label(&self) -> Option<String> {
+        match self.current_image() {
+            ButtonImage::Text(text) => Some(text.clone()),
+            ButtonImage::SysLoad(widget) => Some(widget.label()),
+            _ => None,
+        }
+    }
+    // Whether touching the button presses it
+    fn interactive(&self) -> bool {
+        (self.action != Key::Unknown && self.action != Key::Time) || self.layer_action.is_some() || self.media.is_some()
+    }
+}
+
+// Paints the image scaled to a size x size square at x, y
+fn paint_image(c: &Context, png: &DynamicImage, x: f64, y: f64, size: f64) {
+    // Resize the PNG image to match the specified size
+    let resized_png = resize(
+        png,
+        size as u32,
+        size as u32,
+        FilterType::Lanczos3,
+    );
+
+    // Convert the resized PNG image to a Cairo ImageSurface
+    let png_surface = ImageSurface::create(
+        Format::ARgb32,
+        size as i32,
+        size as i32,
+    ).expect("Failed to create PNG surface");
+
+    let png_context = Context::new(&png_surface)
+        .expect("Failed to create PNG context");
+
+    // Iterate over the pixels of the resized PNG image and paint them on the Cairo surface
+    for (x_pixel, y_pixel, pixel) in resized_png.enumerate_pixels() {
+        let channels = pixel.channels();
+        let (r, g, b, a) = (channels[0], channels[1], channels[2], channels[3]);
+        let _ = png_context.set_source_rgba(
+            r as f64 / 255.0,
+            g as f64 / 255.0,
+            b as f64 / 255.0,
+            a as f64 / 255.0,
+        );
+        let _ = png_context.rectangle(
+            x_pixel as f64,
+            y_pixel as f64,
+            1.0,
+            1.0,
+        );
+        let _ = png_context.fill();
+    }
+
+    // Composite the PNG surface onto the main context (the `c` context)
+    let _ = c.set_source_surface(&png_surface, x, y);
+    let _ = c.paint().expect("Failed to composite PNG image");
+}
 
-            toggle_key(uinput, self.action, active as i32);
+// Shortens text with an ellipsis until it fits into width
+fn elide(c: &Context, text: &str, width: f64) -> String {
+    let fits = |text: &str| c.text_extents(text).map_or(true, |e| e.width() <= width);
+    if fits(text) {
+        return text.to_string();
+    }
+    let mut chars = text.chars().collect::<Vec<_>>();
+    while !chars.is_empty() {
+        chars.pop();
+        let elided = format!("{}…", chars.iter().collect::<String>().trim_end());
+        if fits(&elided) {
+            return elided;
         }
     }
+    String::new()
+}
+
+// draw box with rounded corners
+fn draw_rounded_box(c: &Context, left_edge: f64, box_width: f64, bot: f64, top: f64, radius: f64) {
+    rounded_box_path(c, left_edge, box_width, bot, top, radius);
+    c.fill().unwrap();
+}
+
+fn rounded_box_path(c: &Context, left_edge: f64, box_width: f64, bot: f64, top: f64, radius: f64) {
+    c.new_sub_path();
+    let left = left_edge + radius;
+    let right = left_edge + box_width - radius;
+    c.arc(
+        right,
+        bot,
+        radius,
+        (-90.0f64).to_radians(),
+        (0.0f64).to_radians(),
+    );
+    c.arc(
+        right,
+        top,
+        radius,
+        (0.0f64).to_radians(),
+        (90.0f64).to_radians(),
+    );
+    c.arc(
+        left,
+        top,
+        radius,
+        (90.0f64).to_radians(),
+        (180.0f64).to_radians(),
+    );
+    c.arc(
+        left,
+        bot,
+        radius,
+        (180.0f64).to_radians(),
+        (270.0f64).to_radians(),
+    );
+    c.close_path();
 }
 
 struct FunctionLayer {
+    // configured name, used to switch to the layer from the control socket
+    name: String,
     buttons: Vec<Button>,
+    i3bar: Option<I3bar>,
+    // number of trailing buttons generated from i3bar blocks
+    blocks: usize,
+    // slider currently being dragged, drawn across the whole bar
+    expanded: Option<usize>,
+    // buttons never get narrower than this, the layer scrolls instead
+    min_button_width: f64,
+    scroll: Scroll,
+    // popover layers return to the previous layer after being idle this long
+    popover_timeout: Option<Duration>,
 }
 
 impl FunctionLayer {
+    // Returns true if the buttons were regenerated and the layer has to be redrawn
+    fn update(&mut self) -> bool {
+        for button in &mut self.buttons {
+            button.update();
+        }
+        let scrolled = self.scroll.tick();
+        let Some(i3bar) = &mut self.i3bar else {
+            return scrolled;
+        };
+        if !i3bar.update() {
+            return scrolled;
+        }
+        self.buttons.truncate(self.buttons.len() - self.blocks);
+        self.blocks = i3bar.blocks().len();
+        for block in i3bar.blocks() {
+            self.buttons.push(Button::new_block(&block.text, block.color));
+        }
+        true
+    }
+    // Returns the button width and the spacing between buttons
+    fn layout(&self, width: u16) -> (f64, f64) {
+        let count = self.buttons.len() as f64;
+        let button_width = width as f64 / (count + 1.0);
+        if self.buttons.len() < 2 {
+            (button_width, 0.0)
+        } else if button_width < self.min_button_width {
+            (self.min_button_width, self.min_button_width / 8.0)
+        } else {
+            (button_width, (width as f64 - count * button_width) / (count - 1.0))
+        }
+    }
+    fn scrollable(&self, width: u16) -> bool {
+        let (button_width, spacing_width) = self.layout(width);
+        let count = self.buttons.len() as f64;
+        count * button_width + (count - 1.0) * spacing_width > width as f64
+    }
+    fn left_edge(&self, btn: usize, width: u16) -> f64 {
+        let (button_width, spacing_width) = self.layout(width);
+        btn as f64 * (button_width + spacing_width) - self.scroll.offset()
+    }
+    fn button_hit(&self, btn: usize, width: u16, height: u16, x: f64, y: f64) -> bool {
+        let (button_width, _) = self.layout(width);
+        let left_edge = self.left_edge(btn, width);
+        if x < left_edge || x > (left_edge + button_width) {
+            return false;
+        }
+        y > 0.09 * height as f64 && y < 0.91 * height as f64
+    }
+    fn hit(&self, width: u16, height: u16, x: f64, y: f64) -> Option<usize> {
+        let (button_width, spacing_width) = self.layout(width);
+        let btn = ((x + self.scroll.offset()) / (button_width + spacing_width)).max(0.0) as usize;
+        (btn < self.buttons.len() && self.button_hit(btn, width, height, x, y)).then_some(btn)
+    }
+    fn block_index(&self, btn: usize) -> Option<usize> {
+        let first_block = self.buttons.len() - self.blocks;
+        (self.i3bar.is_some() && btn >= first_block).then(|| btn - first_block)
+    }
+    fn click_block(&mut self, block: usize, x: f64, y: f64) {
+        if let Some(i3bar) = &mut self.i3bar {
+            i3bar.click(block, x, y);
+        }
+    }
+    fn expand(&mut self, btn: usize, position: f64) {
+        if let Some(slider) = &mut self.buttons[btn].slider {
+            slider.start_drag(position);
+            self.expanded = Some(btn);
+        }
+    }
+    fn collapse(&mut self) {
+        if let Some(btn) = self.expanded.take() {
+            if let Some(slider) = &mut self.buttons[btn].slider {
+                slider.end_drag();
+            }
+        }
+    }
+    fn draw_expanded(&mut self, c: &Context, btn: usize, height: i32, width: i32) -> Vec<ClipRect> {
+        let radius = 8.0f64;
+        let bot = (height as f64) * 0.15;
+        let top = (height as f64) * 0.85;
+        let (button_width, _) = self.layout(width as u16);
+        let button = &mut self.buttons[btn];
+        let value = button.slider.as_ref().unwrap().value();
+        c.set_source_rgb(0.0, 0.0, 0.0);
+        c.paint().unwrap();
+        c.set_source_rgb(BUTTON_COLOR_INACTIVE, BUTTON_COLOR_INACTIVE, BUTTON_COLOR_INACTIVE);
+        draw_rounded_box(c, 0.0, width as f64, bot, top, radius);
+        // keep the filled part at least as wide as its rounded corners
+        let filled = (width as f64 * value).max(radius * 2.0);
+        c.set_source_rgb(BUTTON_COLOR_ACTIVE, BUTTON_COLOR_ACTIVE, BUTTON_COLOR_ACTIVE);
+        draw_rounded_box(c, 0.0, filled, bot, top, radius);
+        c.set_source_rgb(1.0, 1.0, 1.0);
+        button.render(c, height as f64, 0.0, button_width);
+        button.changed = false;
+        vec![ClipRect {
+            x1: 0,
+            y1: 0,
+            x2: height as u16,
+            y2: width as u16,
+        }]
+    }
+    fn next_update(&self) -> Option<Instant> {
+        self.buttons
+            .iter()
+            .filter_map(|button| button.next_update())
+            .chain(self.i3bar.as_ref().and_then(|i3bar| i3bar.next_update()))
+            .chain(self.scroll.next_update())
+            .min()
+    }
+    fn poll_fds(&self) -> Vec<RawFd> {
+        self.buttons
+            .iter()
+            .filter_map(|button| button.poll_fd())
+            .chain(self.i3bar.as_ref().and_then(|i3bar| i3bar.poll_fd()))
+            .collect()
+    }
     fn draw(
         &mut self,
         surface: &ImageSurface,
@@ -280,9 +898,16 @@ impl FunctionLayer {
         let width = surface.height();
         c.translate(height as f64, 0.0);
         c.rotate((90.0f64).to_radians());
-        let button_width = width as f64 / (self.buttons.len() + 1) as f64;
-        let spacing_width = (width as f64 - self.buttons.len() as f64 * button_width)
-            / (self.buttons.len() - 1) as f64;
+        c.select_font_face(&config.ui.font, FontSlant::Normal, FontWeight::Normal);
+        c.set_font_size(32.0);
+        if let Some(btn) = self.expanded {
+            return self.draw_expanded(&c, btn, height, width);
+        }
+        let (button_width, spacing_width) = self.layout(width as u16);
+        let count = self.buttons.len() as f64;
+        let content_width = count * button_width + (count - 1.0).max(0.0) * spacing_width;
+        self.scroll.set_max_offset(content_width - width as f64);
+        let offset = self.scroll.offset();
         let radius = 8.0f64;
         let bot = (height as f64) * 0.15;
         let top = (height as f64) * 0.85;
@@ -290,14 +915,18 @@ impl FunctionLayer {
             c.set_source_rgb(0.0, 0.0, 0.0);
             c.paint().unwrap();
         }
-        c.select_font_face(&config.ui.font, FontSlant::Normal, FontWeight::Normal);
-        c.set_font_size(32.0);
         for (i, button) in self.buttons.iter_mut().enumerate() {
             if !button.changed && !complete_redraw {
                 continue;
             };
 
-            let left_edge = i as f64 * (button_width + spacing_width);
+            let left_edge = i as f64 * (button_width + spacing_width) - offset;
+            let span = if button.action == Key::Time { 3.0 } else { 1.0 };
+            if left_edge + button_width * span < 0.0 || left_edge > width as f64 {
+                // only buttons scrolled into view are rendered
+                button.changed = false;
+                continue;
+            }
             if !complete_redraw {
                 c.set_source_rgb(0.0, 0.0, 0.0);
                 if button.action == Key::Time {
@@ -323,7 +952,7 @@ impl FunctionLayer {
                 BUTTON_COLOR_INACTIVE
             };
 
-            if (button.action != Key::Time &&
+            if ((button.action != Key::Time &&
                button.action != Key::Unknown &&
                button.action != Key::Macro1 &&
                button.action != Key::Macro2 &&
@@ -337,66 +966,68 @@ impl FunctionLayer {
                 button.action != Key::Prog2 &&
                 button.action != Key::Prog3 &&
                 button.action != Key::Prog4) ||
-                button.active) {
-                // draw box with rounded corners
+                button.active)) || button.slider.is_some() || button.layer_action.is_some() || button.media.is_some() {
                 c.set_source_rgb(color, color, color);
-                c.new_sub_path();
-                let left = left_edge + radius;
-                let right = left_edge + button_width - radius;
-                c.arc(
-                    right,
-                    bot,
-                    radius,
-                    (-90.0f64).to_radians(),
-                    (0.0f64).to_radians(),
-                );
-                c.arc(
-                    right,
-                    top,
-                    radius,
-                    (0.0f64).to_radians(),
-                    (90.0f64).to_radians(),
-                );
-                c.arc(
-                    left,
-                    top,
-                    radius,
-                    (90.0f64).to_radians(),
-                    (180.0f64).to_radians(),
-                );
-                c.arc(
-                    left,
-                    bot,
-                    radius,
-                    (180.0f64).to_radians(),
-                    (270.0f64).to_radians(),
-                );
-                c.close_path();
-                c.fill().unwrap();
+                draw_rounded_box(&c, left_edge, button_width, bot, top, radius);
             }
-            c.set_source_rgb(1.0, 1.0, 1.0);
+            let (r, g, b) = button.current_color().unwrap_or((1.0, 1.0, 1.0));
+            c.set_source_rgb(r, g, b);
             if button.action == Key::Time {
                 button.render(&c, height as f64, left_edge, button_width * 3.0);
             } else {
                 button.render(&c, height as f64, left_edge, button_width);
             }
+            button.render_badge(&c, height as f64, left_edge, button_width * span);
+            if button.led.as_ref().is_some_and(|led| led.lit()) {
+                // a lit lock key gets a light below its label, like on the keyboard
+                c.set_source_rgb(0.3, 0.85, 0.4);
+                c.rectangle(
+                    left_edge + button_width * 3.0 / 8.0,
+                    top,
+                    button_width / 4.0,
+                    radius / 2.0,
+                );
+                c.fill().unwrap();
+            }
+            if let Some(slider) = &button.slider {
+                // show the current level as a bar below the icon
+                c.rectangle(
+                    left_edge + radius,
+                    top,
+                    (button_width - radius * 2.0) * slider.value(),
+                    radius / 2.0,
+                );
+                c.fill().unwrap();
+            }
 
             button.changed = false;
-            if button.action == Key::Time {
+            // partially visible buttons only dirty the visible part
             modified_regions.push(ClipRect {
                 x1: height as u16 - top as u16 - radius as u16,
-                y1: left_edge as u16,
+                y1: left_edge.max(0.0) as u16,
                 x2: height as u16 - bot as u16 + radius as u16,
-                y2: left_edge as u16 + button_width as u16 * 3,
+                y2: (left_edge + button_width * span).min(width as f64) as u16,
             });
-            } else {
+        }
+
+        // indicate that more buttons are available off screen
+        let indicator_width = radius / 2.0;
+        for (visible, left_edge) in [
+            (!self.scroll.at_start(), 0.0),
+            (!self.scroll.at_end(), width as f64 - indicator_width),
+        ] {
+            if !visible {
+                continue;
+            }
+            c.set_source_rgb(BUTTON_COLOR_ACTIVE, BUTTON_COLOR_ACTIVE, BUTTON_COLOR_ACTIVE);
+            c.rectangle(left_edge, bot, indicator_width, top - bot);
+            c.fill().unwrap();
             modified_regions.push(ClipRect {
-                x1: height as u16 - top as u16 - radius as u16,
+                x1: height as u16 - top as u16,
                 y1: left_edge as u16,
-                x2: height as u16 - bot as u16 + radius as u16,
-                y2: left_edge as u16 + button_width as u16,
+                x2: height as u16 - bot as u16,
+                y2: (left_edge + indicator_width) as u16,
             });
-            }
         }
 
         if complete_redraw {
@@ -412,6 +1043,52 @@ impl FunctionLayer {
     }
 }
 
+struct Touch {
+    layer: usize,
+    btn: Option<usize>,
+    start_x: f64,
+    // presses on scrollable layers are deferred until the touch
+    // turns out not to be a scroll
+    pending: Option<Instant>,
+    scrolling: bool,
+}
+
+struct Popover {
+    layer: usize,
+    timeout: Duration,
+    deadline: Instant,
+}
+
+// text covering the whole bar for a while, set over the control socket
+// or mirrored from a desktop notification
+struct Message {
+    text: String,
+    until: Instant,
+    // notification closed when the message is tapped
+    notification: Option<u32>,
+    shown: Instant,
+    // set while drawing, text wider than the bar keeps being redrawn
+    scrolling: bool,
+}
+
+impl Message {
+    fn new(text: String, timeout: Duration, notification: Option<u32>) -> Message {
+        Message {
+            text,
+            until: Instant::now() + timeout,
+            notification,
+            shown: Instant::now(),
+            scrolling: false,
+        }
+    }
+    fn next_update(&self) -> Instant {
+        match self.scrolling {
+            true => self.until.min(Instant::now() + MARQUEE_FRAME_TIME),
+            false => self.until,
+        }
+    }
+}
+
 struct Interface;
 
 impl LibinputInterface for Interface {
@@ -431,16 +1108,6 @@ impl LibinputInterface for Interface {
     }
 }
 
-fn button_hit(num: u32, idx: u32, width: u16, height: u16, x: f64, y: f64) -> bool {
-    let button_width = width as f64 / (num + 1) as f64;
-    let spacing_width = (width as f64 - num as f64 * button_width) / (num - 1) as f64;
-    let left_edge = idx as f64 * (button_width + spacing_width);
-    if x < left_edge || x > (left_edge + button_width) {
-        return false;
-    }
-    y > 0.09 * height as f64 && y < 0.91 * height as f64
-}
-
 fn emit<F>(uinput: &mut UInputHandle<F>, ty: EventKind, code: u16, value: i32)
 where
     F: AsRawFd,
@@ -471,8 +1138,19 @@ where
     );
 }
 
+fn tap_key<F>(uinput: &mut UInputHandle<F>, code: Key)
+where
+    F: AsRawFd,
+{
+    toggle_key(uinput, code, 1);
+    toggle_key(uinput, code, 0);
+}
+
 #[derive(Deserialize)]
 struct ButtonConfig {
+    // lets external programs update the button over the control socket
+    #[serde(default)]
+    name: String,
     #[serde(default)]
     label: String,
     #[serde(default)]
@@ -480,14 +1158,101 @@ struct ButtonConfig {
     mode: String,
     #[serde(default)]
     theme: String,
+    #[serde(default = "default_interval")]
+    interval: f64,
+    #[serde(default)]
+    style: String,
+    #[serde(default)]
+    sensor: String,
+    #[serde(default)]
+    command: String,
+    #[serde(default)]
+    continuous: bool,
+    #[serde(default)]
+    json: bool,
+    #[serde(default = "default_steps")]
+    steps: u32,
+    #[serde(default)]
+    decrease_key: String,
+    #[serde(default)]
+    increase_key: String,
+    #[serde(default)]
+    setter: String,
+    #[serde(default)]
+    getter: String,
+    #[serde(default)]
+    backlight: String,
+    // handled by the daemon itself, "back" returns to the previous layer
+    #[serde(default)]
+    action: String,
+    // contents of a popover group
+    #[serde(default)]
+    buttons: Vec<ButtonConfig>,
+    #[serde(default = "default_popover_timeout")]
+    timeout: f64,
+    // switches the label and color of app, media and text buttons with a toggle
+    status: Option<StatusConfig>,
 }
 
 #[derive(Deserialize)]
-struct LayerButtonsConfig {
-    buttons: Vec<ButtonConfig>,
+struct StatusConfig {
+    // the state comes from one of: a LED in /sys/class/leds like
+    // "platform::micmute", a command printing e.g. "yes" or "no", or a
+    // D-Bus property like "org.bluez.Adapter1.Powered"
+    #[serde(default)]
+    led: String,
+    #[serde(default)]
+    command: String,
+    #[serde(default)]
+    continuous: bool,
+    #[serde(default = "default_interval")]
+    interval: f64,
+    #[serde(default)]
+    #[cfg_attr(not(feature = "dbus"), allow(dead_code))]
+    property: String,
+    #[serde(default)]
+    #[cfg_attr(not(feature = "dbus"), allow(dead_code))]
+    service: String,
+    #[serde(default)]
+    #[cfg_attr(not(feature = "dbus"), allow(dead_code))]
+    path: String,
+    // system bus when empty, "session" or an address
+    #[serde(default)]
+    #[cfg_attr(not(feature = "dbus"), allow(dead_code))]
+    bus: String,
+    // label while on, the button's own label is shown while off
+    #[serde(default)]
+    on_label: String,
+    #[serde(default)]
+    on_color: String,
+    #[serde(default)]
+    off_color: String,
 }
 
-#[derive(Deserialize)]
+fn default_interval() -> f64 {
+    1.0
+}
+
+fn default_steps() -> u32 {
+    16
+}
+
+fn default_popover_timeout() -> f64 {
+    5.0
+}
+
+#[derive(Deserialize)]
+struct LayerButtonsConfig {
+    #[serde(default)]
+    name: String,
+    #[serde(default)]
+    buttons: Vec<ButtonConfig>,
+    // status blocks of an i3bar protocol generator are appended to the buttons
+    #[serde(default)]
+    i3bar_command: String,
+}
+
+#[derive(Deserialize)]
 struct LayersConfig {
     primary_layer_buttons: LayerButtonsConfig,
     secondary_layer_buttons: LayerButtonsConfig,
@@ -503,6 +1268,8 @@ enum LayerType {
     Function,
     Special,
     SpecialExtended,
+    SpecialExtended2,
+    SpecialExtended3,
 }
 
 #[derive(Deserialize)]
@@ -512,6 +1279,22 @@ struct UiConfig {
     font: String,
     media_icon_theme: String,
     app_icon_theme: String,
+    #[serde(default = "default_min_button_width")]
+    min_button_width: f64,
+    // layers cycled through by swiping across the bar, empty disables swiping
+    #[serde(default)]
+    swipe_layers: Vec<LayerType>,
+    // double tapping or holding Fn keeps the secondary layer until Fn is tapped again
+    #[serde(default)]
+    fn_lock: bool,
+    // layers shown while modifiers like "ctrl" or "ctrl+shift" are held,
+    // the combination with the most held modifiers wins and Fn beats them all
+    #[serde(default)]
+    modifier_layers: HashMap<String, LayerType>,
+}
+
+fn default_min_button_width() -> f64 {
+    100.0
 }
 
 
@@ -520,16 +1303,220 @@ struct TimeConfig {
     use_24_hr: u16,
 }
 
+#[derive(Deserialize)]
+struct FocusRuleConfig {
+    // regexes matched against the focused window, either one may match
+    #[serde(default)]
+    app_id: String,
+    #[serde(default)]
+    class: String,
+    layer: LayerType,
+}
+
+#[derive(Deserialize, Default)]
+struct FocusConfig {
+    // "sway", "i3" or "hyprland", empty disables focus tracking
+    #[serde(default)]
+    source: String,
+    // compositor socket, found in the logged in user's runtime dir when
+    // empty. A socket given here is opened by the unprivileged daemon user.
+    #[serde(default)]
+    socket: String,
+    #[serde(default)]
+    rules: Vec<FocusRuleConfig>,
+}
+
+#[derive(Deserialize)]
+struct ControlConfig {
+    // empty disables the control socket, changes need a restart
+    #[serde(default = "default_control_socket")]
+    socket: String,
+    // besides root only these users and group members may connect
+    #[serde(default)]
+    allowed_users: Vec<String>,
+    #[serde(default)]
+    allowed_groups: Vec<String>,
+}
+
+fn default_control_socket() -> String {
+    protocol::CONTROL_SOCKET.to_string()
+}
+
+impl Default for ControlConfig {
+    fn default() -> Self {
+        ControlConfig {
+            socket: default_control_socket(),
+            allowed_users: Vec::new(),
+            allowed_groups: Vec::new(),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[cfg_attr(not(feature = "dbus"), allow(dead_code))]
+struct DbusConfig {
+    // only used by builds with the dbus feature, changes need a restart
+    #[serde(default = "default_dbus_enabled")]
+    enabled: bool,
+    // bus to register on instead of the system bus, e.g. a private
+    // dbus-daemon for testing
+    #[serde(default)]
+    address: String,
+}
+
+fn default_dbus_enabled() -> bool {
+    true
+}
+
+impl Default for DbusConfig {
+    fn default() -> Self {
+        DbusConfig {
+            enabled: default_dbus_enabled(),
+            address: String::new(),
+        }
+    }
+}
+
+#[derive(Deserialize, Default)]
+#[cfg_attr(not(feature = "dbus"), allow(dead_code))]
+struct MprisConfig {
+    // session bus of the media players, that of the logged in user when
+    // empty, which is reached through the session helper. A bus given here
+    // is connected to by the unprivileged daemon user.
+    #[serde(default)]
+    bus: String,
+}
+
+#[derive(Deserialize)]
+#[cfg_attr(not(feature = "dbus"), allow(dead_code))]
+struct NotificationsConfig {
+    // shows desktop notifications across the bar, needs the dbus feature
+    #[serde(default)]
+    enabled: bool,
+    // session bus to monitor, that of the logged in user when empty
+    #[serde(default)]
+    bus: String,
+    // seconds a notification stays on the bar unless tapped away
+    #[serde(default = "default_notification_timeout")]
+    timeout: f64,
+}
+
+fn default_notification_timeout() -> f64 {
+    5.0
+}
+
+impl Default for NotificationsConfig {
+    fn default() -> Self {
+        NotificationsConfig {
+            enabled: false,
+            bus: String::new(),
+            timeout: default_notification_timeout(),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct BacklightConfig {
+    // brightness of the bar while in use and after dim_timeout seconds
+    // without touches or key presses, both limited to the device maximum
+    #[serde(default = "default_active_brightness")]
+    active_brightness: u32,
+    #[serde(default = "default_dim_brightness")]
+    dim_brightness: u32,
+    // seconds until the bar dims and turns off, 0 never does
+    #[serde(default = "default_dim_timeout")]
+    dim_timeout: f64,
+    #[serde(default = "default_off_timeout")]
+    off_timeout: f64,
+    // seconds brightness changes take, 0 switches at once. Touches and
+    // key presses always wake the bar at once.
+    #[serde(default = "default_fade_time")]
+    fade_time: f64,
+    // follow the ambient light sensor instead of active_brightness
+    #[serde(default)]
+    auto_brightness: bool,
+    // [lux, brightness] points, brightness is interpolated between them
+    #[serde(default = "default_brightness_curve")]
+    brightness_curve: Vec<(f64, u32)>,
+    // where the sensor is looked for in bus/iio/devices, e.g. a fake tree for testing
+    #[serde(default = "default_sysfs_root")]
+    sysfs_root: String,
+}
+
+fn default_active_brightness() -> u32 {
+    128
+}
+
+fn default_dim_brightness() -> u32 {
+    1
+}
+
+fn default_dim_timeout() -> f64 {
+    30.0
+}
+
+fn default_off_timeout() -> f64 {
+    60.0
+}
+
+fn default_fade_time() -> f64 {
+    0.0
+}
+
+fn default_brightness_curve() -> Vec<(f64, u32)> {
+    vec![(0.0, 16), (20.0, 64), (200.0, 128), (1000.0, 255)]
+}
+
+fn default_sysfs_root() -> String {
+    "/sys".to_string()
+}
+
+impl Default for BacklightConfig {
+    fn default() -> Self {
+        BacklightConfig {
+            active_brightness: default_active_brightness(),
+            dim_brightness: default_dim_brightness(),
+            dim_timeout: default_dim_timeout(),
+            off_timeout: default_off_timeout(),
+            fade_time: default_fade_time(),
+            auto_brightness: false,
+            brightness_curve: default_brightness_curve(),
+            sysfs_root: default_sysfs_root(),
+        }
+    }
+}
+
 #[derive(Deserialize)]
 struct Config {
     ui: UiConfig,
     time: TimeConfig,
     layers: LayersConfig,
+    #[serde(default)]
+    focus: FocusConfig,
+    #[serde(default)]
+    control: ControlConfig,
+    #[serde(default)]
+    backlight: BacklightConfig,
+    #[serde(default)]
+    #[cfg_attr(not(feature = "dbus"), allow(dead_code))]
+    dbus: DbusConfig,
+    #[serde(default)]
+    #[cfg_attr(not(feature = "dbus"), allow(dead_code))]
+    mpris: MprisConfig,
+    #[serde(default)]
+    #[cfg_attr(not(feature = "dbus"), allow(dead_code))]
+    notifications: NotificationsConfig,
 }
 
 impl Config {
     fn from_file(path: &str) -> Result<Self> {
-        toml::from_str(&read_to_string(path)?).map_err(anyhow::Error::from)
+        let config: Config = toml::from_str(&read_to_string(path)?)?;
+        for spec in config.ui.modifier_layers.keys() {
+            if modifiers::parse(spec).is_none() {
+                eprintln!("Could not parse modifiers {}. Ignored!", spec);
+            }
+        }
+        Ok(config)
     }
 }
 
@@ -555,7 +1542,7 @@ lazy_static! {
     };
 }
 
-fn build_layer_vectors(buttons: &Vec<ButtonConfig>, config: &Config) -> Vec<Button> {
+fn build_layer_vectors(buttons: &Vec<ButtonConfig>, config: &Config, popovers: &mut Vec<FunctionLayer>) -> Vec<Button> {
     // helper to poputate layers with the given config
     let mut vector = Vec::new();
     for button_config in buttons {
@@ -563,64 +1550,509 @@ fn build_layer_vectors(buttons: &Vec<ButtonConfig>, config: &Config) -> Vec<Butt
         let key = button_config.key.as_str();
         let theme = button_config.theme.as_str();
         let mode = button_config.mode.as_str();
+        let count = vector.len();
         match mode {
             "blank" => vector.push(Button::new_blank()),
             "time" => vector.push(Button::new_time(config.time.use_24_hr)),
+            "cpu" | "memory" | "temperature" => {
+                let kind = match mode {
+                    "cpu" => SysLoadKind::Cpu,
+                    "memory" => SysLoadKind::Memory,
+                    _ => SysLoadKind::Temperature,
+                };
+                let graph = button_config.style == "graph";
+                match SysLoad::new(kind, &button_config.sensor, button_config.interval, graph) {
+                    Ok(widget) => vector.push(Button::new_sysload(widget)),
+                    Err(e) => eprintln!("Could not set up {} button: {}. Ignored!", mode, e),
+                }
+            },
+            "command_output" => {
+                if button_config.command.is_empty() {
+                    eprintln!("No command set for command_output button {}. Ignored!", label);
+                    continue;
+                }
+                // the key is optional, without one the button only displays output
+                let action = KEY_MAP.get(key).copied().unwrap_or(Key::Unknown);
+                let theme = theme.is_empty().then(|| config.ui.media_icon_theme.as_str()).unwrap_or(theme);
+                let command = CommandOutput::new(
+                    &button_config.command,
+                    button_config.interval,
+                    button_config.continuous,
+                    button_config.json,
+                    theme,
+                );
+                vector.push(Button::new_command(command, action));
+            },
+            "slider" | "brightness" => {
+                let keys = (KEY_MAP.get(&button_config.decrease_key), KEY_MAP.get(&button_config.increase_key));
+                let target = if mode == "brightness" {
+                    match DisplayBacklight::open(&button_config.backlight) {
+                        Ok(backlight) => SliderTarget::Backlight(backlight),
+                        Err(e) => {
+                            eprintln!("Could not set up brightness button {}: {}. Ignored!", label, e);
+                            continue;
+                        }
+                    }
+                } else if let (Some(decrease), Some(increase)) = keys {
+                    if button_config.getter.is_empty() {
+                        eprintln!("Slider button {} with keys needs a getter command to show the level. Ignored!", label);
+                        continue;
+                    }
+                    SliderTarget::Keys(*decrease, *increase)
+                } else if !button_config.setter.is_empty() {
+                    SliderTarget::Command(button_config.setter.clone())
+                } else {
+                    eprintln!("Slider button {} needs either keys or a setter command. Ignored!", label);
+                    continue;
+                };
+                let theme = theme.is_empty().then(|| config.ui.media_icon_theme.as_str()).unwrap_or(theme);
+                let getter = (!button_config.getter.is_empty()).then(|| {
+                    CommandOutput::new(&button_config.getter, button_config.interval, false, false, theme)
+                });
+                let slider = Slider::new(button_config.steps, target, button_config.interval);
+                vector.push(Button::new_slider(label, theme, slider, getter));
+            },
+            "lock_key" => {
+                let led = match KEY_MAP.get(key) {
+                    Some(Key::CapsLock) => "capslock",
+                    Some(Key::NumLock) => "numlock",
+                    Some(Key::ScrollLock) => "scrolllock",
+                    _ => {
+                        eprintln!("Lock key button {} needs Key::CapsLock, Key::NumLock or Key::ScrollLock. Ignored!", label);
+                        continue;
+                    }
+                };
+                let key = KEY_MAP[key];
+                let mut button = match theme {
+                    // without a theme the label is shown as text
+                    "" => Button::new_text(label, key),
+                    theme => Button::new_icon(label, key, theme),
+                };
+                button.led = Some(LedState::new(led));
+                vector.push(button);
+            },
+            "mpris" => {
+                if !cfg!(feature = "dbus") {
+                    eprintln!("Media player button {} needs tiny-dfr built with the dbus feature. Ignored!", label);
+                    continue;
+                }
+                let theme = theme.is_empty().then(|| config.ui.media_icon_theme.as_str()).unwrap_or(theme);
+                let (control, icon) = match button_config.action.as_str() {
+                    "" | "now_playing" => (MediaControl::NowPlaying, None),
+                    "play_pause" => (MediaControl::PlayPause, Some("media-playback-start-symbolic")),
+                    "next" => (MediaControl::Next, Some("media-skip-forward-symbolic")),
+                    "previous" => (MediaControl::Previous, Some("media-skip-backward-symbolic")),
+                    action => {
+                        eprintln!("Could not find action {} for button {}. Ignored!", action, label);
+                        continue;
+                    }
+                };
+                // the label replaces the icons of next and previous, the now
+                // playing text shows it until a player is found
+                let mut button = match icon {
+                    Some(icon) if label.is_empty() || control == MediaControl::PlayPause => Button::new_icon(icon, Key::Unknown, theme),
+                    Some(_) => Button::new_icon(label, Key::Unknown, theme),
+                    None => Button::new_text(label, Key::Unknown),
+                };
+                button.media = Some(MediaButton {
+                    control,
+                    icon_theme: theme.to_string(),
+                    art: None,
+                });
+                vector.push(button);
+            },
+            "popover" => {
+                let theme = theme.is_empty().then(|| config.ui.media_icon_theme.as_str()).unwrap_or(theme);
+                let mut buttons = vec![Button::new_layer_action("window-close-symbolic", "tiny-dfr-icons", LayerAction::Back)];
+                buttons.extend(build_layer_vectors(&button_config.buttons, config, popovers));
+                let mut layer = new_layer(buttons, None, config);
+                layer.popover_timeout = Some(Duration::from_secs_f64(button_config.timeout.max(1.0)));
+                popovers.push(layer);
+                let index = CONFIGURED_LAYERS + popovers.len() - 1;
+                vector.push(Button::new_layer_action(label, theme, LayerAction::Open(index)));
+            },
             "app" | "media" | "text" => {
-                // handle missing input_linux::Keys
+                let layer_action = match button_config.action.as_str() {
+                    "" => None,
+                    "back" => Some(LayerAction::Back),
+                    action => {
+                        eprintln!("Could not find action {} for button {}. Ignored!", action, label);
+                        continue;
+                    }
+                };
+                // handle missing input_linux::Keys, buttons with an action need none
                 let key_map = KEY_MAP.get(key);
-                if key_map == None {
+                if key_map == None && layer_action.is_none() {
                     eprintln!("Could not find input_linux::Key {} for button {}. Ignored!", key, label);
                     continue;
                 }
+                let key = key_map.copied().unwrap_or(Key::Unknown);
+                let mut button;
                 if mode == "app" {
                     // if theme is an empty string assign the global theme
                     let theme = theme.is_empty().then(|| config.ui.app_icon_theme.as_str()).unwrap_or(theme);
-                    vector.push(Button::new_icon(label, *key_map.unwrap(), theme));
+                    button = Button::new_icon(label, key, theme);
                 }
                 else if mode == "media" {
                     // if theme is an empty string assign the global theme
                     let theme = theme.is_empty().then(|| config.ui.media_icon_theme.as_str()).unwrap_or(theme);
-                    vector.push(Button::new_icon(label, *key_map.unwrap(), theme));
-                } else {vector.push(Button::new_text(label, *key_map.unwrap()))};
+                    button = Button::new_icon(label, key, theme);
+                } else {button = Button::new_text(label, key)};
+                button.layer_action = layer_action;
+                if let Some(status) = &button_config.status {
+                    let theme = match mode {
+                        "app" => Some(theme.is_empty().then(|| config.ui.app_icon_theme.as_str()).unwrap_or(theme)),
+                        "media" => Some(theme.is_empty().then(|| config.ui.media_icon_theme.as_str()).unwrap_or(theme)),
+                        _ => None,
+                    };
+                    match build_toggle(status, label, theme) {
+                        Ok(toggle) => {
+                            button.color = toggle.color();
+                            button.toggle = Some(toggle);
+                        }
+                        Err(e) => eprintln!("Could not set up status of button {}: {}. Ignored!", label, e),
+                    }
+                }
+                vector.push(button);
             },
             _ => eprintln!("Could not find mode {} for button {}!", mode, label),
         }
+        if vector.len() > count {
+            vector.last_mut().unwrap().name = button_config.name.clone();
+        }
     }
     vector
 }
 
 
 
-fn initialize_layers(config: &Config) -> [FunctionLayer; 5] {
-    let primary_layer = FunctionLayer {
-        buttons: build_layer_vectors(&config.layers.primary_layer_buttons.buttons, &config),
-    };
+fn build_status_source(status: &StatusConfig) -> Result<StatusSource> {
+    if !status.led.is_empty() {
+        return Ok(StatusSource::Led(LedState::new(&status.led)));
+    }
+    if !status.command.is_empty() {
+        let command = CommandOutput::new(&status.command, status.interval, status.continuous, false, "");
+        return Ok(StatusSource::Command(command));
+    }
+    if !status.property.is_empty() {
+        #[cfg(feature = "dbus")]
+        return Ok(StatusSource::Property(PropertyWatcher::new(
+            &status.bus,
+            &status.service,
+            &status.path,
+            &status.property,
+        )?));
+        #[cfg(not(feature = "dbus"))]
+        return Err(anyhow!("D-Bus properties need tiny-dfr built with the dbus feature"));
+    }
+    Err(anyhow!("no led, command or property set"))
+}
 
-    let secondary_layer = FunctionLayer {
-        buttons: build_layer_vectors(&config.layers.secondary_layer_buttons.buttons, &config),
+fn build_toggle(status: &StatusConfig, label: &str, icon_theme: Option<&str>) -> Result<Toggle> {
+    let parse = |color: &str| match color {
+        "" => Ok(None),
+        color => parse_color(color).map(Some).ok_or(anyhow!("invalid color {}", color)),
     };
-
-    let tertiary_layer = FunctionLayer {
-        buttons: build_layer_vectors(&config.layers.tertiary_layer_buttons.buttons, &config),
+    let mut toggle = Toggle::new(build_status_source(status)?);
+    toggle.off_label = label.to_string();
+    toggle.on_label = match status.on_label.as_str() {
+        "" => label.to_string(),
+        on_label => on_label.to_string(),
     };
+    toggle.on_color = parse(&status.on_color)?;
+    toggle.off_color = parse(&status.off_color)?;
+    toggle.icon_theme = icon_theme.map(|theme| theme.to_string());
+    Ok(toggle)
+}
+
+fn poll_timeout(deadlines: impl Iterator<Item = Instant>) -> i32 {
+    // wake up for the earliest pending widget refresh, deferred press or animation frame, if any
+    match deadlines.min() {
+        Some(deadline) => {
+            let remaining = deadline.saturating_duration_since(Instant::now()).as_millis();
+            remaining.min(TIMEOUT_MS as u128) as i32
+        }
+        None => TIMEOUT_MS,
+    }
+}
 
-    let tertiary2_layer = FunctionLayer {
-        buttons: build_layer_vectors(&config.layers.tertiary2_layer_buttons.buttons, &config),
+// Returns the layer shown normally and the one shown while Fn is held,
+// the Fn lock takes precedence over the focused app's layer
+fn fn_layers(config: &Config, fn_locked: bool, app_layer: Option<usize>) -> (usize, usize) {
+    let primary = config.ui.primary_layer as usize;
+    let secondary = config.ui.secondary_layer as usize;
+    if fn_locked {
+        (secondary, primary)
+    } else {
+        (app_layer.unwrap_or(primary), secondary)
+    }
+}
+
+fn build_ambient_light(config: &BacklightConfig) -> Option<AmbientLight> {
+    if !config.auto_brightness {
+        return None;
+    }
+    AmbientLight::new(&config.sysfs_root, &config.brightness_curve)
+        .map_err(|e| eprintln!("Could not set up auto brightness: {}", e))
+        .ok()
+}
+
+#[cfg(feature = "dbus")]
+fn build_notifications(config: &Config) -> Option<NotificationWatcher> {
+    config.notifications.enabled.then(|| NotificationWatcher::new(&config.notifications.bus))
+}
+
+// Only watches the session bus if there are buttons to show the players on
+#[cfg(feature = "dbus")]
+fn build_mpris(config: &Config, layers: &[FunctionLayer]) -> Option<MprisWatcher> {
+    let used = layers.iter().flat_map(|layer| &layer.buttons).any(|button| button.media.is_some());
+    used.then(|| MprisWatcher::new(&config.mpris.bus))
+}
+
+#[cfg(feature = "dbus")]
+fn show_now_playing(layers: &mut [FunctionLayer], now_playing: Option<&NowPlaying>, art_cache: &mut ArtCache, height: u16) {
+    // the art leaves a fifth of the bar's height free above and below
+    let art_size = (height as f64 * 0.6) as u32;
+    for button in layers.iter_mut().flat_map(|layer| &mut layer.buttons) {
+        let Some(media) = &mut button.media else {
+            continue;
+        };
+        if media.control == MediaControl::NowPlaying {
+            media.art = now_playing.and_then(|track| art_cache.get(&track.art_url, art_size));
+        }
+        button.image = match media.control {
+            MediaControl::NowPlaying => ButtonImage::Text(match now_playing {
+                Some(track) if !track.artist.is_empty() => format!("{} – {}", track.title, track.artist),
+                Some(track) => track.title.clone(),
+                None => String::new(),
+            }),
+            MediaControl::PlayPause => {
+                let playing = now_playing.is_some_and(|track| track.playing);
+                let icon = if playing { "media-playback-pause-symbolic" } else { "media-playback-start-symbolic" };
+                load_icon(icon, &media.icon_theme)
+            }
+            MediaControl::Next | MediaControl::Previous => continue,
+        };
+        button.changed = true;
+    }
+}
+
+fn build_focus(config: &Config) -> (Option<FocusWatcher>, Vec<FocusRule>) {
+    let focus = &config.focus;
+    let compile = |re: &str| -> Option<Regex> {
+        if re.is_empty() {
+            return None;
+        }
+        Regex::new(re)
+            .map_err(|e| eprintln!("Could not parse focus rule {}: {}. Ignored!", re, e))
+            .ok()
+    };
+    let rules = focus
+        .rules
+        .iter()
+        .map(|rule| FocusRule {
+            app_id: compile(&rule.app_id),
+            class: compile(&rule.class),
+            layer: rule.layer as usize,
+        })
+        .collect();
+    // without a source the focus can still be reported over the control socket
+    let watcher = match FocusSource::from_name(&focus.source) {
+        Some(source) => Some(FocusWatcher::new(source, &focus.socket)),
+        None if focus.source.is_empty() => None,
+        None => {
+            eprintln!("Could not find focus source {}. Ignored!", focus.source);
+            None
+        }
     };
+    (watcher, rules)
+}
+
+fn find_layer(layers: &[FunctionLayer], name: &str) -> Option<usize> {
+    layers
+        .iter()
+        .position(|layer| !layer.name.is_empty() && layer.name == name)
+        .or_else(|| {
+            let layer_type = serde_json::from_value::<LayerType>(serde_json::Value::String(name.to_string()));
+            layer_type.ok().map(|layer| layer as usize)
+        })
+}
+
+// Performs what a tapped button does besides emitting its key,
+// returns true if the active layer changed
+fn tap_layer_action(
+    layer_action: Option<LayerAction>,
+    layers: &[FunctionLayer],
+    layer_stack: &mut LayerStack,
+    popovers: &mut Vec<Popover>,
+) -> bool {
+    match layer_action {
+        Some(LayerAction::Open(target)) => {
+            let timeout = layers[target].popover_timeout.unwrap();
+            popovers.push(Popover {
+                layer: target,
+                timeout,
+                deadline: Instant::now() + timeout,
+            });
+            layer_stack.push(target)
+        }
+        Some(LayerAction::Back) => layer_stack.back(),
+        // popovers stay open for repeated taps until closed or idle
+        None => false,
+    }
+}
 
-    let tertiary3_layer = FunctionLayer {
-        buttons: build_layer_vectors(&config.layers.tertiary3_layer_buttons.buttons, &config),
+fn draw_message(surface: &ImageSurface, config: &Config, message: &mut Message) -> Vec<ClipRect> {
+    let c = Context::new(&surface).unwrap();
+    let height = surface.width();
+    let width = surface.height();
+    c.translate(height as f64, 0.0);
+    c.rotate((90.0f64).to_radians());
+    c.set_source_rgb(0.0, 0.0, 0.0);
+    c.paint().unwrap();
+    c.set_source_rgb(BUTTON_COLOR_INACTIVE, BUTTON_COLOR_INACTIVE, BUTTON_COLOR_INACTIVE);
+    draw_rounded_box(&c, 0.0, width as f64, height as f64 * 0.15, height as f64 * 0.85, 8.0);
+    c.select_font_face(&config.ui.font, FontSlant::Normal, FontWeight::Normal);
+    c.set_font_size(32.0);
+    c.set_source_rgb(1.0, 1.0, 1.0);
+    let text = &message.text;
+    let extents = c.text_extents(text).unwrap();
+    let padding = 16.0;
+    let y = height as f64 / 2.0 + extents.height() / 2.0;
+    message.scrolling = extents.width() > width as f64 - padding * 2.0;
+    if message.scrolling {
+        // loop the text around, with a gap between the end and the next start
+        let gap = width as f64 / 3.0;
+        let elapsed = message.shown.elapsed().saturating_sub(MARQUEE_DELAY).as_secs_f64();
+        let offset = (elapsed * MARQUEE_SPEED) % (extents.width() + gap);
+        c.rectangle(padding, 0.0, width as f64 - padding * 2.0, height as f64);
+        c.clip();
+        for x in [padding - offset, padding - offset + extents.width() + gap] {
+            c.move_to(x, y);
+            c.show_text(text).unwrap();
+        }
+    } else {
+        c.move_to(width as f64 / 2.0 - extents.width() / 2.0, y);
+        c.show_text(text).unwrap();
+    }
+    vec![ClipRect {
+        x1: 0,
+        y1: 0,
+        x2: height as u16,
+        y2: width as u16,
+    }]
+}
+
+fn modifier_layer(config: &Config, held: u8) -> Option<usize> {
+    modifiers::select(held, &config.ui.modifier_layers).map(|layer| layer as usize)
+}
+
+// Shows a temporary layer on top of the navigation history, or none
+fn show_overlay(layer_stack: &mut LayerStack, overlay: Option<usize>) -> bool {
+    let before = layer_stack.active();
+    layer_stack.restore();
+    if let Some(layer) = overlay {
+        layer_stack.enter_temporary(layer);
+    }
+    layer_stack.active() != before
+}
+
+fn swipe_target(config: &Config, active_layer: usize, swipe: Swipe) -> Option<usize> {
+    let order = config.ui.swipe_layers.iter().map(|l| *l as usize).collect::<Vec<_>>();
+    if order.is_empty() {
+        return None;
+    }
+    let Some(pos) = order.iter().position(|l| *l == active_layer) else {
+        return Some(order[0]);
     };
+    let next = match swipe {
+        Swipe::Next => (pos + 1) % order.len(),
+        Swipe::Previous => (pos + order.len() - 1) % order.len(),
+    };
+    Some(order[next])
+}
+
+fn present(drm: &mut DrmBackend, surface: &mut ImageSurface, clips: &[ClipRect], pitch: u32, cpp: u32) {
+    let data = surface.data().unwrap();
+    let mut fb = drm.map().unwrap();
+
+    for clip in clips {
+        let base_offset =
+            clip.y1 as usize * pitch as usize + clip.x1 as usize * cpp as usize;
+        let len = (clip.x2 - clip.x1) as usize * cpp as usize;
+
+        for i in 0..(clip.y2 - clip.y1) {
+            let offset = base_offset + i as usize * pitch as usize;
+            let range = offset..(offset + len);
+            fb.as_mut()[range.clone()].copy_from_slice(&data[range]);
+        }
+    }
+
+    drop(fb);
+    drm.dirty(clips).unwrap();
+}
+
+fn new_layer(buttons: Vec<Button>, i3bar: Option<I3bar>, config: &Config) -> FunctionLayer {
+    FunctionLayer {
+        name: String::new(),
+        buttons,
+        i3bar,
+        blocks: 0,
+        expanded: None,
+        min_button_width: config.ui.min_button_width,
+        scroll: Scroll::new(),
+        popover_timeout: None,
+    }
+}
+
+fn build_layer(layer_config: &LayerButtonsConfig, config: &Config, popovers: &mut Vec<FunctionLayer>) -> FunctionLayer {
+    let i3bar = (!layer_config.i3bar_command.is_empty())
+        .then(|| I3bar::new(&layer_config.i3bar_command));
+    let mut layer = new_layer(build_layer_vectors(&layer_config.buttons, &config, popovers), i3bar, config);
+    layer.name = layer_config.name.clone();
+    layer
+}
+
+fn initialize_layers(config: &Config) -> Vec<FunctionLayer> {
+    let mut popovers = Vec::new();
+
+    let primary_layer = build_layer(&config.layers.primary_layer_buttons, &config, &mut popovers);
+
+    let secondary_layer = build_layer(&config.layers.secondary_layer_buttons, &config, &mut popovers);
+
+    let tertiary_layer = build_layer(&config.layers.tertiary_layer_buttons, &config, &mut popovers);
+
+    let tertiary2_layer = build_layer(&config.layers.tertiary2_layer_buttons, &config, &mut popovers);
 
-    [primary_layer, secondary_layer, tertiary_layer, tertiary2_layer, tertiary3_layer]
+    let tertiary3_layer = build_layer(&config.layers.tertiary3_layer_buttons, &config, &mut popovers);
+
+    let mut layers = vec![primary_layer, secondary_layer, tertiary_layer, tertiary2_layer, tertiary3_layer];
+    layers.extend(popovers);
+    layers
 }
 
 fn main() {
     let mut config = Config::from_file(CONFIG_PATH).unwrap();
     let mut last_modified_time = get_file_modified_time(CONFIG_PATH);
+    if let Err(e) = session::spawn_helper() {
+        eprintln!("Failed to start session helper, the user session cannot be reached: {}", e);
+    }
     let mut uinput = UInputHandle::new(OpenOptions::new().write(true).open("/dev/uinput").unwrap());
-    let mut backlight = BacklightManager::new();
+    let mut backlight = BacklightManager::new(&config.backlight);
+    let mut ambient_light = build_ambient_light(&config.backlight);
+    let mut fn_lock = FnLock::new(config.ui.fn_lock);
+    let mut control = (!config.control.socket.is_empty()).then(|| {
+        ControlServer::bind(&config.control.socket, &config.control.allowed_users, &config.control.allowed_groups)
+    }).and_then(|server| {
+        server.map_err(|e| eprintln!("Failed to create control socket: {}", e)).ok()
+    });
+    // the bus only lets root own the name, so connect before dropping privileges
+    #[cfg(feature = "dbus")]
+    let mut dbus = config.dbus.enabled.then(|| {
+        DbusService::connect(&config.dbus.address, &config.control.allowed_users, &config.control.allowed_groups)
+    }).and_then(|service| {
+        service.map_err(|e| eprintln!("Failed to register on D-Bus: {}", e)).ok()
+    });
 
     // drop privileges to input and video group
     let groups = ["input", "video"];
@@ -632,7 +2064,10 @@ fn main() {
         .apply()
         .unwrap_or_else(|e| panic!("Failed to drop privileges: {}", e));
 
-    let mut active_layer = config.ui.primary_layer as usize;
+    let (mut focus, mut focus_rules) = build_focus(&config);
+    // layer picked by the rules for the focused app
+    let mut app_layer: Option<usize> = None;
+    let mut layer_stack = LayerStack::new(fn_layers(&config, fn_lock.locked(), app_layer).0);
     let mut layers = initialize_layers(&config);
 
     let mut needs_complete_redraw = true;
@@ -647,8 +2082,21 @@ fn main() {
             layer.buttons.insert(0, Button::new_text("esc", Key::Esc));
         }
     }
+    // requests address buttons by their index in the configuration,
+    // which does not include the esc key added above
+    let first_configured = usize::from(width >= 2170);
+    #[cfg(feature = "dbus")]
+    let mut mpris = build_mpris(&config, &layers);
+    #[cfg(feature = "dbus")]
+    let mut art_cache = ArtCache::new();
+    #[cfg(feature = "dbus")]
+    let mut notifications = build_notifications(&config);
 
     let mut surface = ImageSurface::create(Format::ARgb32, height as i32, width as i32).unwrap();
+    // composited frames of layer transitions
+    let mut frame = ImageSurface::create(Format::ARgb32, height as i32, width as i32).unwrap();
+    let mut transition: Option<Transition> = None;
+    let mut swipes = SwipeRecognizer::new();
     let mut input_tb = Libinput::new_with_udev(Interface);
     let mut input_main = Libinput::new_with_udev(Interface);
     input_tb.udev_assign_seat("seat-touchbar").unwrap();
@@ -656,9 +2104,19 @@ fn main() {
     let pollfd_tb = PollFd::new(input_tb.as_raw_fd(), PollFlags::POLLIN);
     let pollfd_main = PollFd::new(input_main.as_raw_fd(), PollFlags::POLLIN);
     uinput.set_evbit(EventKind::Key).unwrap();
+    // with LEDs of its own the compositor keeps lock states on the virtual
+    // device in sync, so they show up in sysfs even without physical lights
+    uinput.set_evbit(EventKind::Led).unwrap();
+    for led in [LedKind::NumLock, LedKind::CapsLock, LedKind::ScrollLock] {
+        uinput.set_ledbit(led).unwrap();
+    }
     for layer in &layers {
         for button in &layer.buttons {
             uinput.set_keybit(button.action).unwrap();
+            if let Some((decrease, increase)) = button.slider.as_ref().and_then(|s| s.keys()) {
+                uinput.set_keybit(decrease).unwrap();
+                uinput.set_keybit(increase).unwrap();
+            }
         }
     }
     let mut dev_name_c = [0 as c_char; 80];
@@ -681,13 +2139,42 @@ fn main() {
     uinput.dev_create().unwrap();
 
     let mut digitizer: Option<InputDevice> = None;
-    let mut touches = HashMap::new();
+    let mut touches: HashMap<u32, Touch> = HashMap::new();
+    // open popovers, innermost last
+    let mut popovers: Vec<Popover> = Vec::new();
+    let mut modifiers = Modifiers::new();
+    let mut fn_held = false;
+    // layer shown while Fn or modifiers are held
+    let mut overlay: Option<usize> = None;
+    let mut message: Option<Message> = None;
+    // media buttons tapped this iteration
+    let mut media_taps: Vec<MediaControl> = Vec::new();
+    let mut dismissed_notifications: Vec<u32> = Vec::new();
+    let mut reload_requested = false;
+    // focus reported over the control socket
+    let mut pending_focus: Option<FocusedApp> = None;
+    // last states reported to subscribers
+    let mut reported_layer = layer_stack.active();
+    let mut reported_bl = backlight.current_bl();
     loop {
+        let mut reloaded = false;
         let current_modified_time = get_file_modified_time(CONFIG_PATH);
-        if current_modified_time != last_modified_time {
+        if reload_requested || current_modified_time != last_modified_time {
+            reload_requested = false;
             match Config::from_file(CONFIG_PATH) {
                 Ok(new_config) => {
                     config = new_config;
+                    reloaded = true;
+                    backlight.configure(&config.backlight);
+                    ambient_light = build_ambient_light(&config.backlight);
+                    backlight.set_ambient_bl(None);
+                    if let Some(control) = &mut control {
+                        control.set_allowed(&config.control.allowed_users, &config.control.allowed_groups);
+                    }
+                    #[cfg(feature = "dbus")]
+                    if let Some(dbus) = &mut dbus {
+                        dbus.set_allowed(&config.control.allowed_users, &config.control.allowed_groups);
+                    }
                     last_modified_time = current_modified_time;
                     layers = initialize_layers(&config);
                     if width >= 2170 {
@@ -695,8 +2182,19 @@ fn main() {
                         layer.buttons.insert(0, Button::new_text("esc", Key::Esc));
                         }
                     }
-                    let refreshed_layer = config.ui.primary_layer as usize;
-                    active_layer = refreshed_layer;
+                    #[cfg(feature = "dbus")]
+                    {
+                        mpris = build_mpris(&config, &layers);
+                        notifications = build_notifications(&config);
+                    }
+                    fn_lock.set_enabled(config.ui.fn_lock);
+                    (focus, focus_rules) = build_focus(&config);
+                    app_layer = None;
+                    layer_stack.reset(fn_layers(&config, fn_lock.locked(), app_layer).0);
+                    overlay = None;
+                    touches.clear();
+                    swipes.reset();
+                    popovers.clear();
                     needs_complete_redraw = true;
                 }
                 Err(e) => {
@@ -704,33 +2202,117 @@ fn main() {
                 }
             }
         }
+        let focused = focus
+            .as_mut()
+            .and_then(|focus| focus.update().then(|| focus.focused().cloned()).flatten());
+        if let Some(app) = pending_focus.take().or(focused) {
+            let new_app_layer = focus::matching_layer(&focus_rules, &app);
+            if new_app_layer != app_layer {
+                // switching apps starts over on the app's layer, below whatever is held
+                app_layer = new_app_layer;
+                layer_stack.reset(fn_layers(&config, fn_lock.locked(), app_layer).0);
+                show_overlay(&mut layer_stack, overlay);
+                needs_complete_redraw = true;
+            }
+        }
+        #[cfg(feature = "dbus")]
+        if let Some(mpris) = &mut mpris {
+            if mpris.update() {
+                show_now_playing(&mut layers, mpris.now_playing(), &mut art_cache, height);
+            }
+        }
+        #[cfg(feature = "dbus")]
+        for event in notifications.iter_mut().flat_map(|notifications| notifications.update()) {
+            match event {
+                NotificationEvent::Shown(notification) if !notification.summary.is_empty() => {
+                    let timeout = Duration::from_secs_f64(config.notifications.timeout.max(1.0));
+                    message = Some(Message::new(notification.summary, timeout, Some(notification.id)));
+                    backlight.wake();
+                    needs_complete_redraw = true;
+                }
+                NotificationEvent::Closed(id) if message.as_ref().is_some_and(|m| m.notification == Some(id)) => {
+                    message = None;
+                    needs_complete_redraw = true;
+                }
+                _ => {}
+            }
+        }
+        let active_layer = layer_stack.active();
 	for button in &mut layers[active_layer].buttons {
     	    if button.action == Key::Time {
                 button.changed = true;
     	    }
         }
-        if needs_complete_redraw || layers[active_layer].buttons.iter().any(|b| b.changed) {
-            let clips = layers[active_layer].draw(&surface, &config, needs_complete_redraw);
-            let data = surface.data().unwrap();
-            let mut fb = drm.map().unwrap();
-
-            for clip in &clips {
-                let base_offset =
-                    clip.y1 as usize * pitch as usize + clip.x1 as usize * cpp as usize;
-                let len = (clip.x2 - clip.x1) as usize * cpp as usize;
-
-                for i in 0..(clip.y2 - clip.y1) {
-                    let offset = base_offset + i as usize * pitch as usize;
-                    let range = offset..(offset + len);
-                    fb.as_mut()[range.clone()].copy_from_slice(&data[range]);
+        // LEDs have to be polled, only do so for those that can be seen
+        let bar_on = backlight.current_bl() > 0;
+        for (i, layer) in layers.iter_mut().enumerate() {
+            for button in &mut layer.buttons {
+                button.watch_leds(bar_on && i == active_layer);
+            }
+            if layer.update() && i == active_layer {
+                needs_complete_redraw = true;
+            }
+        }
+        // a scrolling message is redrawn every frame
+        needs_complete_redraw |= message.as_ref().is_some_and(|message| message.scrolling);
+        if needs_complete_redraw || transition.is_some() || layers[active_layer].buttons.iter().any(|b| b.changed) {
+            let clips = match &mut message {
+                Some(message) => draw_message(&surface, &config, message),
+                None => layers[active_layer].draw(&surface, &config, needs_complete_redraw),
+            };
+            match &transition {
+                Some(t) => {
+                    t.compose(&surface, &frame);
+                    let full = [ClipRect { x1: 0, y1: 0, x2: height, y2: width }];
+                    present(&mut drm, &mut frame, &full, pitch, cpp);
+                    if t.done() {
+                        transition = None;
+                    }
                 }
+                None => present(&mut drm, &mut surface, &clips, pitch, cpp),
             }
-
-            drop(fb);
-            drm.dirty(&clips[..]).unwrap();
             needs_complete_redraw = false;
         }
-        poll(&mut [pollfd_tb, pollfd_main], TIMEOUT_MS).unwrap();
+        let mut pollfds = vec![pollfd_tb, pollfd_main];
+        for layer in &layers {
+            for fd in layer.poll_fds() {
+                pollfds.push(PollFd::new(fd, PollFlags::POLLIN));
+            }
+        }
+        if let Some(fd) = focus.as_ref().and_then(|focus| focus.poll_fd()) {
+            pollfds.push(PollFd::new(fd, PollFlags::POLLIN));
+        }
+        for fd in control.iter().flat_map(|control| control.poll_fds()) {
+            pollfds.push(PollFd::new(fd, PollFlags::POLLIN));
+        }
+        #[cfg(feature = "dbus")]
+        if let Some(dbus) = &dbus {
+            pollfds.push(PollFd::new(dbus.poll_fd(), PollFlags::POLLIN));
+        }
+        #[cfg(feature = "dbus")]
+        if let Some(fd) = mpris.as_ref().and_then(|mpris| mpris.poll_fd()) {
+            pollfds.push(PollFd::new(fd, PollFlags::POLLIN));
+        }
+        #[cfg(feature = "dbus")]
+        for fd in notifications.iter().flat_map(|notifications| notifications.poll_fds()) {
+            pollfds.push(PollFd::new(fd, PollFlags::POLLIN));
+        }
+        let deadlines = layers
+            .iter()
+            .filter_map(|layer| layer.next_update())
+            .chain(touches.values().filter_map(|touch| touch.pending))
+            .chain(transition.as_ref().map(|transition| transition.next_update()))
+            .chain(popovers.last().map(|popover| popover.deadline))
+            .chain(fn_lock.next_update())
+            .chain(backlight.next_update())
+            .chain(ambient_light.as_ref().map(|ambient_light| ambient_light.next_update()))
+            .chain(focus.as_ref().and_then(|focus| focus.next_update()))
+            .chain(message.as_ref().map(|message| message.next_update()));
+        #[cfg(feature = "dbus")]
+        let deadlines = deadlines
+            .chain(mpris.as_ref().and_then(|mpris| mpris.next_update()))
+            .chain(notifications.as_ref().and_then(|notifications| notifications.next_update()));
+        poll(&mut pollfds, poll_timeout(deadlines)).unwrap();
         input_tb.dispatch().unwrap();
         input_main.dispatch().unwrap();
         for event in &mut input_tb.clone().chain(input_main.clone()) {
@@ -743,24 +2325,30 @@ fn main() {
                     }
                 }
                 Event::Keyboard(KeyboardEvent::Key(key)) => {
+                    let pressed = key.key_state() == KeyState::Pressed;
+                    if key.key() != Key::Fn as u32 && pressed {
+                        // Fn used as a modifier does not lock
+                        fn_lock.other_key();
+                    }
+                    modifiers.key(key.key(), pressed);
                     if key.key() == Key::Fn as u32 {
-                        let new_layer = match key.key_state() {
-                            KeyState::Pressed => config.ui.secondary_layer as usize,
-                            KeyState::Released => config.ui.primary_layer as usize,
+                        let toggled = match key.key_state() {
+                            KeyState::Pressed => fn_lock.press(),
+                            KeyState::Released => fn_lock.release(),
                         };
-                        if active_layer != new_layer {
-                            active_layer = new_layer;
+                        // a press that toggled the lock already shows its layer
+                        fn_held = pressed && !toggled;
+                        if toggled {
+                            layer_stack.reset(fn_layers(&config, fn_lock.locked(), app_layer).0);
+                            overlay = None;
                             needs_complete_redraw = true;
                         }
                         } else if key.key() == Key::Macro1 as u32 && key.key_state() == KeyState::Pressed {
-                            active_layer = 3;
-                            needs_complete_redraw = true;
+                            needs_complete_redraw |= layer_stack.push(3);
                         } else if key.key() == Key::Macro2 as u32 && key.key_state() == KeyState::Pressed {
-                            active_layer = 2;
-                            needs_complete_redraw = true;
+                            needs_complete_redraw |= layer_stack.push(2);
                         } else if key.key() == Key::Macro3 as u32 && key.key_state() == KeyState::Pressed {
-                            active_layer = 4;
-                            needs_complete_redraw = true;
+                            needs_complete_redraw |= layer_stack.push(4);
                     }
                 }
                 Event::Touch(te) => {
@@ -769,60 +2357,154 @@ fn main() {
                     }
                     match te {
                         TouchEvent::Down(dn) => {
+                            if fn_held {
+                                // picking a key on the bar uses Fn as a modifier too
+                                fn_lock.other_key();
+                            }
+                            if let Some(dismissed) = message.take() {
+                                // the touch only dismisses the message, along with its notification
+                                dismissed_notifications.extend(dismissed.notification);
+                                needs_complete_redraw = true;
+                                continue;
+                            }
+                            let active_layer = layer_stack.active();
+                            let layer = &mut layers[active_layer];
+                            if layer.expanded.is_some() {
+                                continue;
+                            }
                             let x = dn.x_transformed(width as u32);
                             let y = dn.y_transformed(height as u32);
-                            let btn = (x
-                                / (width as f64 / layers[active_layer].buttons.len() as f64))
-                                as u32;
-                            if button_hit(
-                                layers[active_layer].buttons.len() as u32,
-                                btn,
-                                width,
-                                height,
-                                x,
-                                y,
-                            ) {
-                                let button = &mut layers[active_layer].buttons[btn as usize];
-                                if button.action == Key::Unknown || button.action == Key::Time {
-                                    continue;
+                            let scrollable = layer.scrollable(width);
+                            let may_swipe = !config.ui.swipe_layers.is_empty()
+                                && swipes.down(dn.seat_slot(), x, width as f64);
+                            let mut touch = Touch {
+                                layer: active_layer,
+                                btn: None,
+                                start_x: x,
+                                pending: None,
+                                scrolling: false,
+                            };
+                            if scrollable {
+                                layer.scroll.begin(x);
+                            }
+                            let Some(btn) = layer.hit(width, height, x, y) else {
+                                if scrollable || may_swipe {
+                                    touches.insert(dn.seat_slot(), touch);
                                 }
-                                touches.insert(dn.seat_slot(), (active_layer, btn));
-                                layers[active_layer].buttons[btn as usize]
-                                    .set_active(&mut uinput, true);
+                                continue;
+                            };
+                            if let Some(block) = layer.block_index(btn) {
+                                layer.click_block(block, x, y);
+                                continue;
                             }
-                        }
-                        TouchEvent::Motion(mtn) => {
-                            if !touches.contains_key(&mtn.seat_slot()) {
+                            if layer.buttons[btn].slider.is_some() {
+                                layer.expand(btn, x / width as f64);
+                                touch.btn = Some(btn);
+                                touches.insert(dn.seat_slot(), touch);
+                                needs_complete_redraw = true;
                                 continue;
                             }
-
+                            let button = &mut layer.buttons[btn];
+                            if button.interactive() {
+                                touch.btn = Some(btn);
+                                if scrollable || may_swipe {
+                                    touch.pending = Some(Instant::now() + PRESS_DELAY);
+                                } else {
+                                    button.set_active(&mut uinput, true);
+                                }
+                            }
+                            if touch.btn.is_some() || scrollable || may_swipe {
+                                touches.insert(dn.seat_slot(), touch);
+                            }
+                        }
+                        TouchEvent::Motion(mtn) => {
                             let x = mtn.x_transformed(width as u32);
                             let y = mtn.y_transformed(height as u32);
-                            let (layer, btn) = *touches.get(&mtn.seat_slot()).unwrap();
-                            let hit = button_hit(
-                                layers[layer].buttons.len() as u32,
-                                btn,
-                                width,
-                                height,
-                                x,
-                                y,
-                            );
-                                let button = &mut layers[layer].buttons[btn as usize];
-                                if button.action == Key::Unknown || button.action == Key::Time {
-                                    continue;
+                            let swipe = swipes.motion(mtn.seat_slot(), x);
+                            if let Some(new_layer) = swipe.and_then(|s| swipe_target(&config, layer_stack.active(), s)) {
+                                // the gesture takes over, cancel whatever the fingers were doing
+                                for (_, touch) in touches.drain() {
+                                    let layer = &mut layers[touch.layer];
+                                    layer.collapse();
+                                    if let Some(btn) = touch.btn.filter(|_| touch.pending.is_none()) {
+                                        layer.buttons[btn].set_active(&mut uinput, false);
+                                    }
+                                }
+                                // swiping moves sideways, it does not add to the history
+                                if layer_stack.replace(new_layer) {
+                                    transition = Some(Transition::new(&surface, swipe.unwrap()));
+                                    needs_complete_redraw = true;
+                                }
+                                continue;
+                            }
+                            let Some(touch) = touches.get_mut(&mtn.seat_slot()) else {
+                                continue;
+                            };
+                            let layer = &mut layers[touch.layer];
+                            if let Some(btn) = touch.btn.filter(|btn| layer.expanded == Some(*btn)) {
+                                let button = &mut layer.buttons[btn];
+                                let slider = button.slider.as_mut().unwrap();
+                                let steps = slider.set_value(x / width as f64);
+                                if let Some((decrease, increase)) = slider.keys() {
+                                    let key = if steps < 0 { decrease } else { increase };
+                                    for _ in 0..steps.abs() {
+                                        tap_key(&mut uinput, key);
+                                    }
                                 }
-                            button.set_active(&mut uinput, hit);
+                                button.changed = true;
+                                continue;
+                            }
+                            let undecided = touch.pending.is_some() || touch.btn.is_none();
+                            if undecided && !touch.scrolling && (x - touch.start_x).abs() > SCROLL_THRESHOLD {
+                                touch.pending = None;
+                                touch.scrolling = true;
+                            }
+                            if touch.scrolling {
+                                // edge touches are reserved for swiping between layers
+                                let edge = swipes.is_edge_touch(mtn.seat_slot());
+                                if !edge && layer.scroll.drag(x) && touch.layer == layer_stack.active() {
+                                    needs_complete_redraw = true;
+                                }
+                                continue;
+                            }
+                            let Some(btn) = touch.btn.filter(|_| touch.pending.is_none()) else {
+                                continue;
+                            };
+                            let hit = layer.button_hit(btn, width, height, x, y);
+                            layer.buttons[btn].set_active(&mut uinput, hit);
                         }
                         TouchEvent::Up(up) => {
-                            if !touches.contains_key(&up.seat_slot()) {
+                            swipes.up(up.seat_slot());
+                            let Some(touch) = touches.remove(&up.seat_slot()) else {
+                                continue;
+                            };
+                            let layer = &mut layers[touch.layer];
+                            if touch.btn.is_some() && layer.expanded == touch.btn {
+                                layer.collapse();
+                                needs_complete_redraw = true;
+                                continue;
+                            }
+                            if touch.scrolling {
+                                layer.scroll.release();
                                 continue;
                             }
-                            let (layer, btn) = *touches.get(&up.seat_slot()).unwrap();
-                            let button = &mut layers[layer].buttons[btn as usize];
-                            if button.action == Key::Unknown || button.action == Key::Time {
+                            let Some(btn) = touch.btn else {
                                 continue;
+                            };
+                            let button = &mut layer.buttons[btn];
+                            if touch.pending.is_some() {
+                                // a quick tap on a scrollable layer
+                                button.set_active(&mut uinput, true);
                             }
+                            let pressed = button.active;
+                            let layer_action = button.layer_action;
+                            let media = button.media.as_ref().map(|media| media.control);
                             button.set_active(&mut uinput, false);
+                            if !pressed || touch.layer != layer_stack.active() {
+                                continue;
+                            }
+                            media_taps.extend(media);
+                            needs_complete_redraw |= tap_layer_action(layer_action, &layers, &mut layer_stack, &mut popovers);
                         }
                         _ => {}
                     }
@@ -830,6 +2512,224 @@ fn main() {
                 _ => {}
             }
         }
+        // requests from the control socket and D-Bus are handled alike
+        let mut handle_request = |request: Request| match request {
+            Request::Layer { layer } => {
+                if layer == "back" {
+                    needs_complete_redraw |= layer_stack.back();
+                    return Response::ok();
+                }
+                let Some(target) = find_layer(&layers, &layer) else {
+                    return Response::error(&format!("No layer named {}", layer));
+                };
+                needs_complete_redraw |= layer_stack.push(target);
+                Response::ok()
+            }
+            Request::Reload => match Config::from_file(CONFIG_PATH) {
+                // only valid configurations replace the running one
+                Ok(_) => {
+                    reload_requested = true;
+                    Response::ok()
+                }
+                Err(e) => Response::error(&format!("Failed to load configuration: {}", e)),
+            },
+            Request::Press { button } => {
+                let active_layer = layer_stack.active();
+                let Some(target) = layers[active_layer].buttons[first_configured..].get_mut(button).filter(|b| b.interactive()) else {
+                    return Response::error(&format!("No button {} to press", button));
+                };
+                target.set_active(&mut uinput, true);
+                target.set_active(&mut uinput, false);
+                let layer_action = target.layer_action;
+                media_taps.extend(target.media.as_ref().map(|media| media.control));
+                needs_complete_redraw |= tap_layer_action(layer_action, &layers, &mut layer_stack, &mut popovers);
+                Response::ok()
+            }
+            Request::SetLabel { button, text } => {
+                let active_layer = layer_stack.active();
+                let Some(target) = layers[active_layer].buttons[first_configured..].get_mut(button) else {
+                    return Response::error(&format!("No button {}", button));
+                };
+                target.push_content(Some(ButtonImage::Text(text)), None, None, None);
+                Response::ok()
+            }
+            Request::Update { button, text, icon, color, badge, ttl } => {
+                let color = match color.as_deref() {
+                    None => None,
+                    Some("") => Some(None),
+                    Some(color) => match parse_color(color) {
+                        Some(color) => Some(Some(color)),
+                        None => return Response::error(&format!("Invalid color {}", color)),
+                    },
+                };
+                let badge = badge.map(|badge| (!badge.is_empty()).then_some(badge));
+                let ttl = ttl.map(|ttl| Duration::from_secs_f64(ttl.min(86400.0).max(0.1)));
+                let mut found = false;
+                for target in layers.iter_mut().flat_map(|layer| &mut layer.buttons) {
+                    if target.name.is_empty() || target.name != button {
+                        continue;
+                    }
+                    // an icon takes precedence over text
+                    let image = match (&icon, &text) {
+                        (Some(icon), _) => Some(load_icon(icon, &config.ui.media_icon_theme)),
+                        (None, Some(text)) => Some(ButtonImage::Text(text.clone())),
+                        (None, None) => None,
+                    };
+                    target.push_content(image, color, badge.clone(), ttl);
+                    found = true;
+                }
+                if !found {
+                    return Response::error(&format!("No button named {}", button));
+                }
+                Response::ok()
+            }
+            Request::Status => {
+                let active_layer = layer_stack.active();
+                let layer = &layers[active_layer];
+                let buttons = layer.buttons[first_configured..].iter().map(|button| ButtonStatus {
+                    name: (!button.name.is_empty()).then(|| button.name.clone()),
+                    label: button.label(),
+                    active: button.active,
+                });
+                Response {
+                    status: Some(Status {
+                        layer: active_layer,
+                        layer_name: (!layer.name.is_empty()).then(|| layer.name.clone()),
+                        buttons: buttons.collect(),
+                    }),
+                    ..Response::ok()
+                }
+            }
+            Request::Message { text, timeout } => {
+                let timeout = Duration::from_secs_f64(timeout.min(3600.0).max(0.1));
+                message = (!text.is_empty()).then(|| Message::new(text, timeout, None));
+                if message.is_some() {
+                    backlight.wake();
+                }
+                needs_complete_redraw = true;
+                Response::ok()
+            }
+            // the server streams events to the client from now on
+            Request::Subscribe => Response::ok(),
+            Request::Focus { app_id, class } => {
+                pending_focus = Some(FocusedApp { app_id, class });
+                Response::ok()
+            }
+        };
+        if let Some(control) = &mut control {
+            control.process(&mut handle_request);
+        }
+        #[cfg(feature = "dbus")]
+        if let Some(service) = &mut dbus {
+            if let Err(e) = service.process(&mut handle_request) {
+                eprintln!("D-Bus service stopped: {}", e);
+                dbus = None;
+            }
+        }
+        #[cfg(feature = "dbus")]
+        if let Some(mpris) = &mpris {
+            for control in &media_taps {
+                mpris.call(control.method());
+            }
+        }
+        media_taps.clear();
+        #[cfg(feature = "dbus")]
+        if let Some(notifications) = &notifications {
+            for id in &dismissed_notifications {
+                notifications.close(*id);
+            }
+        }
+        dismissed_notifications.clear();
+        if message.as_ref().is_some_and(|message| Instant::now() >= message.until) {
+            message = None;
+            needs_complete_redraw = true;
+        }
+        // touches that stayed still long enough press their button
+        for touch in touches.values_mut() {
+            if touch.pending.is_some_and(|deadline| Instant::now() >= deadline) {
+                touch.pending = None;
+                if let Some(btn) = touch.btn {
+                    layers[touch.layer].buttons[btn].set_active(&mut uinput, true);
+                }
+            }
+        }
+        if fn_lock.update() {
+            // held long enough, the now locked or unlocked layer shows right away
+            layer_stack.reset(fn_layers(&config, fn_lock.locked(), app_layer).0);
+            fn_held = false;
+            overlay = None;
+            needs_complete_redraw = true;
+        }
+        // releasing Fn or the modifiers restores wherever the user was before
+        let held_layer = fn_held
+            .then(|| fn_layers(&config, fn_lock.locked(), app_layer).1)
+            .or_else(|| modifier_layer(&config, modifiers.mask()));
+        if held_layer != overlay {
+            overlay = held_layer;
+            needs_complete_redraw |= show_overlay(&mut layer_stack, overlay);
+        }
+        // forget popovers that were left, and close the innermost one once idle
+        popovers.retain(|p| layer_stack.contains(p.layer));
+        if let Some(p) = popovers.last_mut() {
+            if p.layer != layer_stack.active() || !touches.is_empty() {
+                // a popover in use or hidden under a held layer never times out
+                p.deadline = Instant::now() + p.timeout;
+            } else if Instant::now() >= p.deadline {
+                layer_stack.back();
+                popovers.pop();
+                needs_complete_redraw = true;
+            }
+        }
+        if let Some(brightness) = ambient_light.as_mut().and_then(|ambient_light| ambient_light.update()) {
+            backlight.set_ambient_bl(Some(brightness));
+        }
         backlight.update_backlight();
+
+        // report what happened to subscribers, transitions are always
+        // drained so they do not pile up without any
+        let mut events = Vec::new();
+        if reloaded {
+            events.push(protocol::Event::ConfigReloaded);
+        }
+        for (layer_index, layer) in layers.iter_mut().enumerate() {
+            for (index, button) in layer.buttons.iter_mut().enumerate() {
+                let name = (!button.name.is_empty()).then(|| button.name.clone());
+                let transitions = button.transitions.drain(..);
+                // the added esc key has no index to report
+                let Some(index) = index.checked_sub(first_configured) else {
+                    continue;
+                };
+                for pressed in transitions {
+                    let (layer, button, name) = (layer_index, index, name.clone());
+                    events.push(match pressed {
+                        true => protocol::Event::ButtonPressed { layer, button, name },
+                        false => protocol::Event::ButtonReleased { layer, button, name },
+                    });
+                }
+            }
+        }
+        if layer_stack.active() != reported_layer {
+            reported_layer = layer_stack.active();
+            let name = &layers[reported_layer].name;
+            events.push(protocol::Event::LayerChanged {
+                layer: reported_layer,
+                layer_name: (!name.is_empty()).then(|| name.clone()),
+            });
+        }
+        if !backlight.fading() && backlight.current_bl() != reported_bl {
+            reported_bl = backlight.current_bl();
+            events.push(protocol::Event::BacklightChanged { brightness: reported_bl });
+        }
+        if let Some(control) = &mut control {
+            for event in &events {
+                control.broadcast(event);
+            }
+        }
+        #[cfg(feature = "dbus")]
+        if let Some(dbus) = &mut dbus {
+            for event in &events {
+                dbus.broadcast(event);
+            }
+        }
     }
 }
@@ -0,0 +1,132 @@
+use std::collections::HashMap;
+use input_linux::Key;
+
+const CTRL: u8 = 1 << 0;
+const ALT: u8 = 1 << 1;
+const SHIFT: u8 = 1 << 2;
+const SUPER: u8 = 1 << 3;
+
+fn modifier(key: u32) -> u8 {
+    match key {
+        k if k == Key::LeftCtrl as u32 || k == Key::RightCtrl as u32 => CTRL,
+        k if k == Key::LeftAlt as u32 || k == Key::RightAlt as u32 => ALT,
+        k if k == Key::LeftShift as u32 || k == Key::RightShift as u32 => SHIFT,
+        k if k == Key::LeftMeta as u32 || k == Key::RightMeta as u32 => SUPER,
+        _ => 0,
+    }
+}
+
+// Parses "ctrl", "alt+shift" style modifier combinations
+pub fn parse(spec: &str) -> Option<u8> {
+    let mut mask = 0;
+    for name in spec.split('+') {
+        mask |= match name.trim().to_lowercase().as_str() {
+            "ctrl" | "control" => CTRL,
+            "alt" => ALT,
+            "shift" => SHIFT,
+            "super" | "meta" => SUPER,
+            _ => return None,
+        };
+    }
+    Some(mask)
+}
+
+// Picks the value of the most specific combination contained in the held
+// modifiers, combinations that fail to parse are ignored
+pub fn select<T: Copy>(held: u8, combinations: &HashMap<String, T>) -> Option<T> {
+    if held == 0 {
+        return None;
+    }
+    combinations
+        .iter()
+        .filter_map(|(spec, value)| Some((parse(spec)?, *value)))
+        .filter(|(mask, _)| held & mask == *mask)
+        // ties are broken by the mask so the choice does not depend on map order
+        .max_by_key(|(mask, _)| (mask.count_ones(), *mask))
+        .map(|(_, value)| value)
+}
+
+// Modifiers held on any keyboard, left and right keys are tracked separately
+// so releasing one side keeps the modifier held while the other is down.
+pub struct Modifiers {
+    held: Vec<u32>,
+}
+
+impl Modifiers {
+    pub fn new() -> Modifiers {
+        Modifiers { held: Vec::new() }
+    }
+    pub fn key(&mut self, key: u32, pressed: bool) {
+        if modifier(key) == 0 {
+            return;
+        }
+        self.held.retain(|k| *k != key);
+        if pressed {
+            self.held.push(key);
+        }
+    }
+    pub fn mask(&self) -> u8 {
+        self.held.iter().fold(0, |mask, key| mask | modifier(*key))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn held(keys: &[Key]) -> u8 {
+        let mut modifiers = Modifiers::new();
+        for key in keys {
+            modifiers.key(*key as u32, true);
+        }
+        modifiers.mask()
+    }
+
+    #[test]
+    fn parses_combinations() {
+        assert_eq!(parse("ctrl"), Some(CTRL));
+        assert_eq!(parse("Alt + Shift"), Some(ALT | SHIFT));
+        assert_eq!(parse("control+meta"), Some(CTRL | SUPER));
+        assert_eq!(parse("ctrl+hyper"), None);
+        assert_eq!(parse(""), None);
+    }
+
+    #[test]
+    fn tracks_both_sides() {
+        let mut modifiers = Modifiers::new();
+        modifiers.key(Key::LeftShift as u32, true);
+        modifiers.key(Key::RightShift as u32, true);
+        modifiers.key(Key::LeftShift as u32, false);
+        assert_eq!(modifiers.mask(), SHIFT);
+        modifiers.key(Key::RightShift as u32, false);
+        assert_eq!(modifiers.mask(), 0);
+        // other keys are not modifiers
+        modifiers.key(Key::Esc as u32, true);
+        assert_eq!(modifiers.mask(), 0);
+    }
+
+    #[test]
+    fn selects_most_specific() {
+        let combinations = HashMap::from([
+            ("ctrl".to_string(), 1),
+            ("alt".to_string(), 2),
+            ("ctrl+shift".to_string(), 3),
+            ("bogus".to_string(), 4),
+        ]);
+        assert_eq!(select(0, &combinations), None);
+        assert_eq!(select(held(&[Key::LeftShift]), &combinations), None);
+        assert_eq!(select(held(&[Key::RightCtrl]), &combinations), Some(1));
+        assert_eq!(select(held(&[Key::LeftCtrl, Key::LeftShift]), &combinations), Some(3));
+        assert_eq!(select(held(&[Key::LeftCtrl, Key::LeftMeta]), &combinations), Some(1));
+    }
+
+    #[test]
+    fn ties_do_not_depend_on_order() {
+        let combinations = HashMap::from([("ctrl".to_string(), 1), ("alt".to_string(), 2)]);
+        for _ in 0..8 {
+            // a fresh map may iterate in another order
+            let combinations = combinations.clone().into_iter().collect::<HashMap<_, _>>();
+            assert_eq!(select(held(&[Key::LeftCtrl, Key::LeftAlt]), &combinations), Some(2));
+        }
+    }
+}
@@ -0,0 +1,375 @@
+use std::{
+    os::fd::RawFd,
+    time::{Duration, Instant},
+};
+use anyhow::Result;
+use dbus::{
+    arg::{ArgType, PropMap, RefArg},
+    channel::Channel,
+    message::MessageType,
+    Message,
+};
+use crate::{reconnect::Reconnect, session};
+
+const CALL_TIMEOUT: Duration = Duration::from_secs(2);
+const DBUS: &str = "org.freedesktop.DBus";
+const DBUS_PATH: &str = "/org/freedesktop/DBus";
+const PROPERTIES: &str = "org.freedesktop.DBus.Properties";
+const PLAYER_PREFIX: &str = "org.mpris.MediaPlayer2.";
+const PLAYER_PATH: &str = "/org/mpris/MediaPlayer2";
+const PLAYER_INTERFACE: &str = "org.mpris.MediaPlayer2.Player";
+
+#[derive(Clone, Default, PartialEq)]
+pub struct NowPlaying {
+    pub title: String,
+    pub artist: String,
+    // mpris:artUrl, usually a file:// URL
+    pub art_url: String,
+    pub playing: bool,
+}
+
+struct Player {
+    // unique bus name, signals are sent from it rather than the well-known name
+    owner: String,
+    state: NowPlaying,
+}
+
+fn bus_call(member: &str) -> Message {
+    Message::new_method_call(DBUS, DBUS_PATH, DBUS, member).unwrap()
+}
+
+// Values nested in a dict are wrapped in a variant of their own
+fn unwrap_variant(arg: &dyn RefArg) -> Option<&dyn RefArg> {
+    match arg.arg_type() {
+        ArgType::Variant => arg.as_iter()?.next(),
+        _ => Some(arg),
+    }
+}
+
+fn read_metadata(metadata: &dyn RefArg, state: &mut NowPlaying) {
+    let Some(mut entries) = unwrap_variant(metadata).and_then(|m| m.as_iter()) else {
+        return;
+    };
+    state.title.clear();
+    state.artist.clear();
+    state.art_url.clear();
+    while let (Some(key), Some(value)) = (entries.next(), entries.next()) {
+        let Some(value) = unwrap_variant(value) else {
+            continue;
+        };
+        match key.as_str() {
+            Some("xesam:title") => state.title = value.as_str().unwrap_or("").to_string(),
+            Some("mpris:artUrl") => state.art_url = value.as_str().unwrap_or("").to_string(),
+            Some("xesam:artist") => {
+                let artists = value.as_iter().into_iter().flatten().filter_map(|a| a.as_str());
+                state.artist = artists.collect::<Vec<_>>().join(", ");
+            }
+            _ => {}
+        }
+    }
+}
+
+// Follows MPRIS media players on the session bus
+pub struct MprisWatcher {
+    address: String,
+    channel: Option<Channel>,
+    // players that started playing most recently first
+    players: Vec<Player>,
+    // GetAll calls waiting for their reply
+    pending: Vec<u32>,
+    reconnect: Reconnect,
+    shown: Option<NowPlaying>,
+}
+
+impl MprisWatcher {
+    // An empty address looks for the session bus of the logged in user
+    pub fn new(address: &str) -> MprisWatcher {
+        MprisWatcher {
+            address: address.to_string(),
+            channel: None,
+            players: Vec::new(),
+            pending: Vec::new(),
+            reconnect: Reconnect::new(),
+            shown: None,
+        }
+    }
+    fn connect(&mut self) -> Result<()> {
+        let address = match self.address.as_str() {
+            "" => session::bus_address(),
+            address => address.to_string(),
+        };
+        let mut channel = Channel::open_private(&address)?;
+        channel.register()?;
+        channel.set_watch_enabled(true);
+        let rules = [
+            format!("type='signal',interface='{}',member='PropertiesChanged',path='{}'", PROPERTIES, PLAYER_PATH),
+            format!("type='signal',interface='{}',member='NameOwnerChanged',arg0namespace='org.mpris.MediaPlayer2'", DBUS),
+        ];
+        for rule in rules {
+            channel.send_with_reply_and_block(bus_call("AddMatch").append1(rule), CALL_TIMEOUT)?;
+        }
+        let names: Vec<String> = channel.send_with_reply_and_block(bus_call("ListNames"), CALL_TIMEOUT)?.read1()?;
+        self.players.clear();
+        self.pending.clear();
+        self.channel = Some(channel);
+        for name in names.iter().filter(|name| name.starts_with(PLAYER_PREFIX)) {
+            self.request_state(name);
+        }
+        Ok(())
+    }
+    // Asks a player for its current state, the reply arrives in update
+    fn request_state(&mut self, name: &str) {
+        let Some(channel) = &self.channel else {
+            return;
+        };
+        let Ok(message) = Message::new_method_call(name, PLAYER_PATH, PROPERTIES, "GetAll") else {
+            return;
+        };
+        if let Ok(serial) = channel.send(message.append1(PLAYER_INTERFACE)) {
+            self.pending.push(serial);
+        }
+    }
+    fn apply(&mut self, owner: &str, properties: &PropMap, create: bool) {
+        let index = match self.players.iter().position(|p| p.owner == owner) {
+            Some(index) => index,
+            None if create => {
+                self.players.push(Player {
+                    owner: owner.to_string(),
+                    state: NowPlaying::default(),
+                });
+                self.players.len() - 1
+            }
+            None => return,
+        };
+        let state = &mut self.players[index].state;
+        let was_playing = state.playing;
+        if let Some(status) = properties.get("PlaybackStatus").and_then(|s| s.0.as_str()) {
+            state.playing = status == "Playing";
+        }
+        if let Some(metadata) = properties.get("Metadata") {
+            read_metadata(&metadata.0, state);
+        }
+        if state.playing && !was_playing {
+            // the player that started last is the one shown
+            let player = self.players.remove(index);
+            self.players.insert(0, player);
+        }
+    }
+    fn handle(&mut self, message: &Message) {
+        let sender = message.sender().map(|s| s.to_string()).unwrap_or_default();
+        match message.msg_type() {
+            MessageType::MethodReturn | MessageType::Error => {
+                let Some(serial) = message.get_reply_serial() else {
+                    return;
+                };
+                let Some(index) = self.pending.iter().position(|s| *s == serial) else {
+                    return;
+                };
+                self.pending.remove(index);
+                if let Ok(properties) = message.read1::<PropMap>() {
+                    self.apply(&sender, &properties, true);
+                }
+            }
+            MessageType::Signal => match message.member().as_deref() {
+                Some("PropertiesChanged") => {
+                    if let Ok((interface, changed)) = message.read2::<String, PropMap>() {
+                        if interface == PLAYER_INTERFACE {
+                            self.apply(&sender, &changed, false);
+                        }
+                    }
+                }
+                Some("NameOwnerChanged") => {
+                    let Ok((name, old, new)) = message.read3::<String, String, String>() else {
+                        return;
+                    };
+                    if !name.starts_with(PLAYER_PREFIX) {
+                        return;
+                    }
+                    if !old.is_empty() {
+                        self.players.retain(|p| p.owner != old);
+                    }
+                    if !new.is_empty() {
+                        self.request_state(&name);
+                    }
+                }
+                _ => {}
+            },
+            _ => {}
+        }
+    }
+    // The player shown, a playing one if there is any
+    pub fn now_playing(&self) -> Option<&NowPlaying> {
+        self.player().map(|p| &p.state)
+    }
+    fn player(&self) -> Option<&Player> {
+        self.players.iter().find(|p| p.state.playing).or(self.players.first())
+    }
+    // Calls a method like "PlayPause" or "Next" on the shown player
+    pub fn call(&self, method: &str) {
+        let (Some(channel), Some(player)) = (&self.channel, self.player()) else {
+            return;
+        };
+        let Ok(mut message) = Message::new_method_call(player.owner.as_str(), PLAYER_PATH, PLAYER_INTERFACE, method) else {
+            return;
+        };
+        message.set_no_reply(true);
+        let _ = channel.send(message);
+        channel.flush();
+    }
+    // Connects when due and consumes pending messages, returns true if the
+    // shown player or its state changed
+    pub fn update(&mut self) -> bool {
+        if self.channel.is_none() && self.reconnect.due() {
+            let connected = self.connect();
+            self.reconnect.attempted(connected, "Failed to connect to session bus for media players");
+        }
+        let mut messages = Vec::new();
+        if let Some(channel) = &self.channel {
+            if channel.read_write(Some(Duration::ZERO)).is_ok() {
+                while let Some(message) = channel.pop_message() {
+                    messages.push(message);
+                }
+            } else {
+                eprintln!("Lost connection to session bus");
+                self.channel = None;
+                self.players.clear();
+                self.reconnect.disconnected();
+            }
+        }
+        for message in &messages {
+            self.handle(message);
+        }
+        let shown = self.now_playing().cloned();
+        if shown == self.shown {
+            return false;
+        }
+        self.shown = shown;
+        true
+    }
+    pub fn next_update(&self) -> Option<Instant> {
+        match self.channel {
+            Some(_) => None,
+            None => Some(self.reconnect.next_attempt()),
+        }
+    }
+    pub fn poll_fd(&self) -> Option<RawFd> {
+        self.channel.as_ref().map(|channel| channel.watch().fd)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+    use dbus::arg::Variant;
+    use crate::test_bus::TestBus;
+
+    // A media player answering GetAll and taking note of the other calls
+    struct MockPlayer {
+        channel: Channel,
+        status: &'static str,
+        calls: Vec<String>,
+    }
+
+    impl MockPlayer {
+        fn start(bus: &TestBus) -> MockPlayer {
+            let channel = bus.channel();
+            let request = bus_call("RequestName").append2("org.mpris.MediaPlayer2.mock", 0u32);
+            channel.send_with_reply_and_block(request, CALL_TIMEOUT).unwrap();
+            MockPlayer {
+                channel,
+                status: "Playing",
+                calls: Vec::new(),
+            }
+        }
+        fn properties(&self) -> PropMap {
+            let mut metadata = PropMap::new();
+            metadata.insert("xesam:title".to_string(), Variant(Box::new("Song".to_string())));
+            metadata.insert("xesam:artist".to_string(), Variant(Box::new(vec!["A".to_string(), "B".to_string()])));
+            metadata.insert("mpris:artUrl".to_string(), Variant(Box::new("file:///tmp/art.png".to_string())));
+            let mut properties = PropMap::new();
+            properties.insert("PlaybackStatus".to_string(), Variant(Box::new(self.status.to_string())));
+            properties.insert("Metadata".to_string(), Variant(Box::new(metadata)));
+            properties
+        }
+        fn serve(&mut self) {
+            self.channel.read_write(Some(Duration::ZERO)).unwrap();
+            while let Some(message) = self.channel.pop_message() {
+                if message.msg_type() != MessageType::MethodCall {
+                    continue;
+                }
+                let member = message.member().unwrap().to_string();
+                let reply = match member.as_str() {
+                    "GetAll" => message.method_return().append1(self.properties()),
+                    _ => {
+                        self.calls.push(member);
+                        message.method_return()
+                    }
+                };
+                if !message.get_no_reply() {
+                    self.channel.send(reply).unwrap();
+                }
+            }
+            self.channel.flush();
+        }
+        fn set_status(&mut self, status: &'static str) {
+            self.status = status;
+            let mut changed = PropMap::new();
+            changed.insert("PlaybackStatus".to_string(), Variant(Box::new(status.to_string())));
+            let signal = Message::new_signal(PLAYER_PATH, PROPERTIES, "PropertiesChanged")
+                .unwrap()
+                .append3(PLAYER_INTERFACE, changed, Vec::<String>::new());
+            self.channel.send(signal).unwrap();
+            self.channel.flush();
+        }
+    }
+
+    // Lets the player and watcher talk until the condition holds
+    fn run_until(watcher: &mut MprisWatcher, player: &mut Option<MockPlayer>, done: impl Fn(&MprisWatcher) -> bool) {
+        for _ in 0..200 {
+            if let Some(player) = player {
+                player.serve();
+            }
+            watcher.update();
+            if done(watcher) {
+                return;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        panic!("watcher did not get there");
+    }
+
+    #[test]
+    fn follows_a_player() {
+        let Some(bus) = TestBus::start() else { return };
+        let mut player = Some(MockPlayer::start(&bus));
+        let mut watcher = MprisWatcher::new(&bus.address);
+        run_until(&mut watcher, &mut player, |w| w.now_playing().is_some());
+        let expected = NowPlaying {
+            title: "Song".to_string(),
+            artist: "A, B".to_string(),
+            art_url: "file:///tmp/art.png".to_string(),
+            playing: true,
+        };
+        assert!(watcher.now_playing() == Some(&expected));
+
+        watcher.call("PlayPause");
+        let mock = player.as_mut().unwrap();
+        for _ in 0..200 {
+            mock.serve();
+            if !mock.calls.is_empty() {
+                break;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(mock.calls, ["PlayPause"]);
+
+        mock.set_status("Paused");
+        run_until(&mut watcher, &mut player, |w| w.now_playing().is_some_and(|p| !p.playing));
+        assert_eq!(watcher.now_playing().unwrap().title, "Song");
+
+        // the player quitting drops it
+        player = None;
+        run_until(&mut watcher, &mut player, |w| w.now_playing().is_none());
+    }
+}
@@ -0,0 +1,150 @@
+// deep enough for any sensible nesting, keeps runaway configs bounded
+const MAX_DEPTH: usize = 16;
+
+// History of visited layers, the active layer is on top
+pub struct LayerStack {
+    stack: Vec<usize>,
+    // stack to restore once a temporary layer such as the Fn layer is left
+    saved: Option<Vec<usize>>,
+}
+
+impl LayerStack {
+    pub fn new(base: usize) -> LayerStack {
+        LayerStack {
+            stack: vec![base],
+            saved: None,
+        }
+    }
+    pub fn active(&self) -> usize {
+        *self.stack.last().unwrap()
+    }
+    pub fn contains(&self, layer: usize) -> bool {
+        self.stack.contains(&layer)
+    }
+    // Returns true if the active layer changed
+    pub fn push(&mut self, layer: usize) -> bool {
+        // revisiting a layer unwinds to it instead of growing the
+        // history, so buttons linking two layers back and forth work
+        if let Some(pos) = self.stack.iter().position(|l| *l == layer) {
+            let changed = pos != self.stack.len() - 1;
+            self.stack.truncate(pos + 1);
+            return changed;
+        }
+        if self.stack.len() == MAX_DEPTH {
+            self.stack.remove(0);
+        }
+        self.stack.push(layer);
+        true
+    }
+    // Returns to the previous layer, the bottom of the stack is never popped
+    pub fn back(&mut self) -> bool {
+        if self.stack.len() < 2 {
+            return false;
+        }
+        self.stack.pop();
+        true
+    }
+    // Switches the active layer without recording it in the history
+    pub fn replace(&mut self, layer: usize) -> bool {
+        let changed = self.active() != layer;
+        self.stack.pop();
+        self.push(layer);
+        changed
+    }
+    pub fn reset(&mut self, base: usize) {
+        self.stack = vec![base];
+        self.saved = None;
+    }
+    // Shows a layer until restore() is called, navigation in between is discarded
+    pub fn enter_temporary(&mut self, layer: usize) -> bool {
+        if self.saved.is_none() {
+            self.saved = Some(self.stack.clone());
+        }
+        self.push(layer)
+    }
+    pub fn restore(&mut self) -> bool {
+        let Some(saved) = self.saved.take() else {
+            return false;
+        };
+        let changed = saved.last() != self.stack.last();
+        self.stack = saved;
+        changed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn push_and_back() {
+        let mut stack = LayerStack::new(0);
+        assert!(!stack.back());
+        assert!(stack.push(1));
+        assert!(stack.push(2));
+        assert_eq!(stack.active(), 2);
+        assert!(stack.contains(1));
+        assert!(stack.back());
+        assert_eq!(stack.active(), 1);
+        assert!(stack.back());
+        assert_eq!(stack.active(), 0);
+        assert!(!stack.back());
+        assert_eq!(stack.active(), 0);
+    }
+
+    #[test]
+    fn revisiting_unwinds() {
+        let mut stack = LayerStack::new(0);
+        stack.push(1);
+        stack.push(2);
+        assert!(!stack.push(2));
+        assert!(stack.push(1));
+        assert!(!stack.contains(2));
+        stack.back();
+        assert_eq!(stack.active(), 0);
+    }
+
+    #[test]
+    fn depth_is_bounded() {
+        let mut stack = LayerStack::new(0);
+        for layer in 1..MAX_DEPTH * 2 {
+            stack.push(layer);
+        }
+        assert_eq!(stack.stack.len(), MAX_DEPTH);
+        assert!(!stack.contains(0));
+        assert_eq!(stack.active(), MAX_DEPTH * 2 - 1);
+    }
+
+    #[test]
+    fn replace_and_reset() {
+        let mut stack = LayerStack::new(0);
+        stack.push(1);
+        assert!(stack.replace(2));
+        assert!(!stack.replace(2));
+        assert!(!stack.contains(1));
+        stack.back();
+        assert_eq!(stack.active(), 0);
+        stack.push(3);
+        stack.enter_temporary(4);
+        stack.reset(1);
+        assert_eq!(stack.active(), 1);
+        assert!(!stack.back());
+        assert!(!stack.restore());
+    }
+
+    #[test]
+    fn temporary_layers_restore() {
+        let mut stack = LayerStack::new(0);
+        stack.push(1);
+        assert!(stack.enter_temporary(5));
+        // navigation on the temporary layer is discarded
+        stack.push(2);
+        stack.enter_temporary(6);
+        assert!(stack.restore());
+        assert_eq!(stack.active(), 1);
+        assert!(!stack.contains(2));
+        assert!(!stack.restore());
+        stack.enter_temporary(1);
+        assert!(!stack.restore());
+    }
+}
@@ -0,0 +1,174 @@
+use std::{
+    os::fd::RawFd,
+    time::{Duration, Instant},
+};
+use anyhow::{Result, anyhow};
+use dbus::{channel::Channel, message::MessageType, Message};
+use crate::{reconnect::Reconnect, session};
+
+const CALL_TIMEOUT: Duration = Duration::from_secs(2);
+// Notify calls of misbehaving clients that never get a reply are forgotten
+const MAX_PENDING: usize = 16;
+const DBUS: &str = "org.freedesktop.DBus";
+const DBUS_PATH: &str = "/org/freedesktop/DBus";
+const NOTIFICATIONS: &str = "org.freedesktop.Notifications";
+const NOTIFICATIONS_PATH: &str = "/org/freedesktop/Notifications";
+
+pub struct Notification {
+    pub id: u32,
+    pub summary: String,
+}
+
+pub enum NotificationEvent {
+    Shown(Notification),
+    Closed(u32),
+}
+
+struct PendingNotify {
+    sender: String,
+    serial: u32,
+    summary: String,
+}
+
+fn open(address: &str) -> Result<Channel> {
+    let mut channel = Channel::open_private(address)?;
+    channel.register()?;
+    channel.set_watch_enabled(true);
+    Ok(channel)
+}
+
+// Mirrors notifications sent to the desktop's notification daemon. The
+// daemon keeps its bus name, a monitor connection sees the Notify calls
+// and their replies while a second one closes notifications.
+pub struct NotificationWatcher {
+    address: String,
+    monitor: Option<Channel>,
+    control: Option<Channel>,
+    pending: Vec<PendingNotify>,
+    reconnect: Reconnect,
+}
+
+impl NotificationWatcher {
+    // An empty address looks for the session bus of the logged in user
+    pub fn new(address: &str) -> NotificationWatcher {
+        NotificationWatcher {
+            address: address.to_string(),
+            monitor: None,
+            control: None,
+            pending: Vec::new(),
+            reconnect: Reconnect::new(),
+        }
+    }
+    fn connect(&mut self) -> Result<()> {
+        let address = match self.address.as_str() {
+            "" => session::bus_address(),
+            address => address.to_string(),
+        };
+        let control = open(&address)?;
+        let monitor = open(&address)?;
+        let rules = vec![
+            format!("type='method_call',interface='{}',member='Notify'", NOTIFICATIONS),
+            // the reply carries the id, there is no way to match it more narrowly
+            "type='method_return'".to_string(),
+            format!("type='signal',interface='{}',member='NotificationClosed'", NOTIFICATIONS),
+        ];
+        let become_monitor = Message::new_method_call(DBUS, DBUS_PATH, "org.freedesktop.DBus.Monitoring", "BecomeMonitor")
+            .map_err(|e| anyhow!(e))?
+            .append2(rules, 0u32);
+        monitor.send_with_reply_and_block(become_monitor, CALL_TIMEOUT)?;
+        self.monitor = Some(monitor);
+        self.control = Some(control);
+        self.pending.clear();
+        Ok(())
+    }
+    fn handle(&mut self, message: &Message) -> Option<NotificationEvent> {
+        match message.msg_type() {
+            MessageType::MethodCall => {
+                // app_name, replaces_id, app_icon, summary, ...
+                let mut args = message.iter_init();
+                let (_, _, _, summary) = (
+                    args.read::<String>().ok()?,
+                    args.read::<u32>().ok()?,
+                    args.read::<String>().ok()?,
+                    args.read::<String>().ok()?,
+                );
+                if self.pending.len() >= MAX_PENDING {
+                    self.pending.remove(0);
+                }
+                self.pending.push(PendingNotify {
+                    sender: message.sender()?.to_string(),
+                    serial: message.get_serial()?,
+                    summary,
+                });
+                None
+            }
+            MessageType::MethodReturn => {
+                let destination = message.destination()?.to_string();
+                let serial = message.get_reply_serial()?;
+                let index = self
+                    .pending
+                    .iter()
+                    .position(|p| p.serial == serial && p.sender == destination)?;
+                let pending = self.pending.remove(index);
+                Some(NotificationEvent::Shown(Notification {
+                    id: message.read1().ok()?,
+                    summary: pending.summary,
+                }))
+            }
+            MessageType::Signal if message.member().as_deref() == Some("NotificationClosed") => {
+                Some(NotificationEvent::Closed(message.read1().ok()?))
+            }
+            _ => None,
+        }
+    }
+    // Connects when due and returns the notifications shown and closed since
+    pub fn update(&mut self) -> Vec<NotificationEvent> {
+        if self.monitor.is_none() && self.reconnect.due() {
+            let connected = self.connect();
+            self.reconnect.attempted(connected, "Failed to monitor notifications on session bus");
+        }
+        let mut messages = Vec::new();
+        let mut connected = true;
+        if let (Some(monitor), Some(control)) = (&self.monitor, &self.control) {
+            connected = monitor.read_write(Some(Duration::ZERO)).is_ok()
+                && control.read_write(Some(Duration::ZERO)).is_ok();
+            while let Some(message) = monitor.pop_message() {
+                messages.push(message);
+            }
+            // nothing is expected here, but it must not pile up
+            while control.pop_message().is_some() {}
+        }
+        if !connected {
+            eprintln!("Lost connection to session bus");
+            self.monitor = None;
+            self.control = None;
+            self.reconnect.disconnected();
+        }
+        messages.iter().filter_map(|message| self.handle(message)).collect()
+    }
+    pub fn close(&self, id: u32) {
+        let Some(control) = &self.control else {
+            return;
+        };
+        let Ok(message) = Message::new_method_call(NOTIFICATIONS, NOTIFICATIONS_PATH, NOTIFICATIONS, "CloseNotification") else {
+            return;
+        };
+        let mut message = message.append1(id);
+        message.set_no_reply(true);
+        let _ = control.send(message);
+        control.flush();
+    }
+    pub fn next_update(&self) -> Option<Instant> {
+        match self.monitor {
+            Some(_) => None,
+            None => Some(self.reconnect.next_attempt()),
+        }
+    }
+    pub fn poll_fds(&self) -> Vec<RawFd> {
+        self.monitor
+            .iter()
+            .chain(self.control.iter())
+            .map(|channel| channel.watch().fd)
+            .collect()
+    }
+}
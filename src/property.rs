@@ -0,0 +1,174 @@
+use std::{
+    os::fd::RawFd,
+    time::{Duration, Instant},
+};
+use anyhow::{Result, anyhow};
+use dbus::{
+    arg::{PropMap, RefArg, Variant},
+    channel::{BusType, Channel},
+    message::MessageType,
+    Message,
+};
+use crate::{reconnect::Reconnect, session, status::parse_state};
+
+const CALL_TIMEOUT: Duration = Duration::from_secs(2);
+const DBUS: &str = "org.freedesktop.DBus";
+const DBUS_PATH: &str = "/org/freedesktop/DBus";
+const PROPERTIES: &str = "org.freedesktop.DBus.Properties";
+
+// Booleans and numbers are on when non-zero, strings like parse_state
+fn truthy(value: &dyn RefArg) -> bool {
+    value
+        .as_f64()
+        .map(|v| v != 0.0)
+        .or_else(|| value.as_str().map(parse_state))
+        .unwrap_or(false)
+}
+
+// Follows a single D-Bus property, like org.bluez.Adapter1.Powered
+pub struct PropertyWatcher {
+    bus: String,
+    service: String,
+    path: String,
+    interface: String,
+    property: String,
+    channel: Option<Channel>,
+    // Get call waiting for its reply
+    pending: Option<u32>,
+    value: Option<bool>,
+    reconnect: Reconnect,
+}
+
+impl PropertyWatcher {
+    // The bus is the system bus when empty, the logged in user's session bus
+    // for "session" or an address. The property includes its interface.
+    pub fn new(bus: &str, service: &str, path: &str, property: &str) -> Result<PropertyWatcher> {
+        let (interface, name) = property
+            .rsplit_once('.')
+            .ok_or(anyhow!("property {} has no interface", property))?;
+        Ok(PropertyWatcher {
+            bus: bus.to_string(),
+            service: service.to_string(),
+            path: path.to_string(),
+            interface: interface.to_string(),
+            property: name.to_string(),
+            channel: None,
+            pending: None,
+            value: None,
+            reconnect: Reconnect::new(),
+        })
+    }
+    fn connect(&mut self) -> Result<()> {
+        let mut channel = match self.bus.as_str() {
+            "" => Channel::get_private(BusType::System)?,
+            bus => {
+                let address = match bus {
+                    "session" => session::bus_address(),
+                    address => address.to_string(),
+                };
+                let mut channel = Channel::open_private(&address)?;
+                channel.register()?;
+                channel
+            }
+        };
+        channel.set_watch_enabled(true);
+        let rules = [
+            format!(
+                "type='signal',sender='{}',path='{}',interface='{}',member='PropertiesChanged',arg0='{}'",
+                self.service, self.path, PROPERTIES, self.interface
+            ),
+            // the service may come and go, e.g. with a restart
+            format!("type='signal',interface='{}',member='NameOwnerChanged',arg0='{}'", DBUS, self.service),
+        ];
+        for rule in rules {
+            let add_match = Message::new_method_call(DBUS, DBUS_PATH, DBUS, "AddMatch").unwrap().append1(rule);
+            channel.send_with_reply_and_block(add_match, CALL_TIMEOUT)?;
+        }
+        self.channel = Some(channel);
+        self.request_value();
+        Ok(())
+    }
+    // Asks for the current value, the reply arrives in update
+    fn request_value(&mut self) {
+        let Some(channel) = &self.channel else {
+            return;
+        };
+        let Ok(message) = Message::new_method_call(self.service.as_str(), self.path.as_str(), PROPERTIES, "Get") else {
+            return;
+        };
+        self.pending = channel.send(message.append2(&self.interface, &self.property)).ok();
+    }
+    fn handle(&mut self, message: &Message) {
+        match message.msg_type() {
+            MessageType::MethodReturn | MessageType::Error => {
+                if message.get_reply_serial().is_none() || message.get_reply_serial() != self.pending {
+                    return;
+                }
+                self.pending = None;
+                // an error means the service is not running or lacks the property
+                self.value = Some(
+                    message
+                        .read1::<Variant<Box<dyn RefArg>>>()
+                        .is_ok_and(|value| truthy(&value.0)),
+                );
+            }
+            MessageType::Signal => match message.member().as_deref() {
+                Some("PropertiesChanged") => {
+                    let Ok((_, changed, invalidated)) = message.read3::<String, PropMap, Vec<String>>() else {
+                        return;
+                    };
+                    if let Some(value) = changed.get(&self.property) {
+                        self.value = Some(truthy(&value.0));
+                    } else if invalidated.contains(&self.property) {
+                        self.request_value();
+                    }
+                }
+                Some("NameOwnerChanged") => {
+                    let Ok((_, _, new)) = message.read3::<String, String, String>() else {
+                        return;
+                    };
+                    match new.is_empty() {
+                        true => self.value = Some(false),
+                        false => self.request_value(),
+                    }
+                }
+                _ => {}
+            },
+            _ => {}
+        }
+    }
+    // Connects when due and consumes pending messages, returns the value
+    // once it is known and every time it is reported again
+    pub fn update(&mut self) -> Option<bool> {
+        if self.channel.is_none() && self.reconnect.due() {
+            let connected = self.connect();
+            let what = format!("Failed to watch D-Bus property {}.{}", self.interface, self.property);
+            self.reconnect.attempted(connected, &what);
+        }
+        let mut messages = Vec::new();
+        if let Some(channel) = &self.channel {
+            if channel.read_write(Some(Duration::ZERO)).is_ok() {
+                while let Some(message) = channel.pop_message() {
+                    messages.push(message);
+                }
+            } else {
+                eprintln!("Lost connection to bus of D-Bus property {}.{}", self.interface, self.property);
+                self.channel = None;
+                self.reconnect.disconnected();
+            }
+        }
+        for message in &messages {
+            self.handle(message);
+        }
+        self.value.take()
+    }
+    pub fn next_update(&self) -> Option<Instant> {
+        match self.channel {
+            Some(_) => None,
+            None => Some(self.reconnect.next_attempt()),
+        }
+    }
+    pub fn poll_fd(&self) -> Option<RawFd> {
+        self.channel.as_ref().map(|channel| channel.watch().fd)
+    }
+}
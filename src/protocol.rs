@@ -0,0 +1,202 @@
+// Control socket protocol, shared by the daemon and tiny-dfr-ctl.
+// Every request and response is a single line of JSON.
+use serde::{Deserialize, Serialize};
+
+pub const CONTROL_SOCKET: &str = "/run/tiny-dfr/control.sock";
+
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "command", rename_all = "snake_case")]
+pub enum Request {
+    // switches to a layer by its configured name or type, "back" returns
+    // to the previous one
+    Layer { layer: String },
+    Reload,
+    // taps a button of the active layer, counted from the left
+    Press { button: usize },
+    // replaces the content of a button of the active layer with text
+    SetLabel { button: usize, text: String },
+    // changes the content of every button with the given name, reverting
+    // to the configured content after ttl seconds if set. An empty color
+    // or badge removes it.
+    Update {
+        button: String,
+        text: Option<String>,
+        icon: Option<String>,
+        color: Option<String>,
+        badge: Option<String>,
+        ttl: Option<f64>,
+    },
+    Status,
+    // shows a message across the whole bar, an empty text hides it
+    Message {
+        text: String,
+        #[serde(default = "default_message_timeout")]
+        timeout: f64,
+    },
+    // turns the connection into a stream of events
+    Subscribe,
+    // focused window reported by a helper instead of the compositor
+    Focus {
+        #[serde(default)]
+        app_id: String,
+        #[serde(default)]
+        class: String,
+    },
+}
+
+fn default_message_timeout() -> f64 {
+    3.0
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ButtonStatus {
+    pub name: Option<String>,
+    // text shown on the button, if it is not an icon
+    pub label: Option<String>,
+    pub active: bool,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Status {
+    pub layer: usize,
+    // configured name of the layer, if any
+    pub layer_name: Option<String>,
+    pub buttons: Vec<ButtonStatus>,
+}
+
+// Sent to subscribed clients, one per line
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event {
+    ButtonPressed {
+        layer: usize,
+        button: usize,
+        name: Option<String>,
+    },
+    ButtonReleased {
+        layer: usize,
+        button: usize,
+        name: Option<String>,
+    },
+    LayerChanged {
+        layer: usize,
+        layer_name: Option<String>,
+    },
+    // raw backlight value, 0 when the bar is off
+    BacklightChanged { brightness: u32 },
+    ConfigReloaded,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Response {
+    pub ok: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub status: Option<Status>,
+}
+
+impl Response {
+    pub fn ok() -> Response {
+        Response {
+            ok: true,
+            error: None,
+            status: None,
+        }
+    }
+    pub fn error(error: &str) -> Response {
+        Response {
+            ok: false,
+            error: Some(error.to_string()),
+            status: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(request: &Request) -> Request {
+        serde_json::from_str(&serde_json::to_string(request).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn requests_round_trip() {
+        let update = Request::Update {
+            button: "build".to_string(),
+            text: Some("Build ✔".to_string()),
+            icon: None,
+            color: Some("#33d17a".to_string()),
+            badge: Some(String::new()),
+            ttl: Some(10.0),
+        };
+        match round_trip(&update) {
+            Request::Update { button, text, icon, color, badge, ttl } => {
+                assert_eq!(button, "build");
+                assert_eq!(text.as_deref(), Some("Build ✔"));
+                assert_eq!(icon, None);
+                assert_eq!(color.as_deref(), Some("#33d17a"));
+                assert_eq!(badge.as_deref(), Some(""));
+                assert_eq!(ttl, Some(10.0));
+            }
+            _ => panic!("update changed its command"),
+        }
+        assert!(matches!(round_trip(&Request::SetLabel { button: 3, text: "x".to_string() }),
+            Request::SetLabel { button: 3, text } if text == "x"));
+        assert!(matches!(round_trip(&Request::Status), Request::Status));
+        assert!(matches!(round_trip(&Request::Reload), Request::Reload));
+    }
+
+    #[test]
+    fn requests_use_snake_case_commands() {
+        let line = serde_json::to_string(&Request::SetLabel { button: 3, text: "x".to_string() }).unwrap();
+        assert_eq!(line, r#"{"command":"set_label","button":3,"text":"x"}"#);
+        let request: Request = serde_json::from_str(r#"{"command":"layer","layer":"media"}"#).unwrap();
+        assert!(matches!(request, Request::Layer { layer } if layer == "media"));
+    }
+
+    #[test]
+    fn optional_fields_default() {
+        let request: Request = serde_json::from_str(r#"{"command":"message","text":"hi"}"#).unwrap();
+        assert!(matches!(request, Request::Message { timeout, .. } if timeout == 3.0));
+        let request: Request = serde_json::from_str(r#"{"command":"focus","app_id":"firefox"}"#).unwrap();
+        assert!(matches!(request, Request::Focus { class, .. } if class.is_empty()));
+        assert!(serde_json::from_str::<Request>(r#"{"command":"explode"}"#).is_err());
+    }
+
+    #[test]
+    fn responses_round_trip() {
+        assert_eq!(serde_json::to_string(&Response::ok()).unwrap(), r#"{"ok":true}"#);
+        let response: Response = serde_json::from_str(&serde_json::to_string(&Response::error("No layer")).unwrap()).unwrap();
+        assert!(!response.ok);
+        assert_eq!(response.error.as_deref(), Some("No layer"));
+        let status = Response {
+            ok: true,
+            error: None,
+            status: Some(Status {
+                layer: 2,
+                layer_name: Some("media".to_string()),
+                buttons: vec![ButtonStatus {
+                    name: None,
+                    label: Some("F1".to_string()),
+                    active: true,
+                }],
+            }),
+        };
+        let response: Response = serde_json::from_str(&serde_json::to_string(&status).unwrap()).unwrap();
+        let status = response.status.unwrap();
+        assert_eq!(status.layer, 2);
+        assert_eq!(status.layer_name.as_deref(), Some("media"));
+        assert_eq!(status.buttons[0].label.as_deref(), Some("F1"));
+        assert!(status.buttons[0].active);
+    }
+
+    #[test]
+    fn events_are_tagged() {
+        let line = serde_json::to_string(&Event::BacklightChanged { brightness: 128 }).unwrap();
+        assert_eq!(line, r#"{"event":"backlight_changed","brightness":128}"#);
+        let event: Event = serde_json::from_str(r#"{"event":"config_reloaded"}"#).unwrap();
+        assert!(matches!(event, Event::ConfigReloaded));
+    }
+}
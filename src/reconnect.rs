@@ -0,0 +1,44 @@
+use std::time::{Duration, Instant};
+use anyhow::Result;
+
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+
+// Paces the connection attempts of a watcher, which keeps retrying for as
+// long as the daemon runs since the other end may come and go with the session
+pub struct Reconnect {
+    next_attempt: Instant,
+    // avoid repeating the same error every reconnect attempt
+    warned: bool,
+}
+
+impl Reconnect {
+    pub fn new() -> Reconnect {
+        Reconnect {
+            next_attempt: Instant::now(),
+            warned: false,
+        }
+    }
+    pub fn due(&self) -> bool {
+        Instant::now() >= self.next_attempt
+    }
+    // Reports a failed attempt unless the previous one failed as well
+    pub fn attempted(&mut self, result: Result<()>, what: &str) {
+        match result {
+            Ok(()) => self.warned = false,
+            Err(e) => {
+                if !self.warned {
+                    eprintln!("{}: {}", what, e);
+                    self.warned = true;
+                }
+                self.disconnected();
+            }
+        }
+    }
+    // The next attempt waits a while after losing the connection
+    pub fn disconnected(&mut self) {
+        self.next_attempt = Instant::now() + RECONNECT_DELAY;
+    }
+    pub fn next_attempt(&self) -> Instant {
+        self.next_attempt
+    }
+}
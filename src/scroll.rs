@@ -0,0 +1,190 @@
+use std::time::{Duration, Instant};
+
+// fraction of the fling velocity lost per second
+const FRICTION: f64 = 0.95;
+const MIN_VELOCITY: f64 = 30.0;
+const FRAME_TIME: Duration = Duration::from_millis(16);
+
+pub struct Scroll {
+    offset: f64,
+    max_offset: f64,
+    // pixels per second, positive values scroll towards the end
+    velocity: f64,
+    last_x: f64,
+    last_time: Instant,
+    momentum: bool,
+}
+
+impl Scroll {
+    pub fn new() -> Scroll {
+        Scroll {
+            offset: 0.0,
+            max_offset: 0.0,
+            velocity: 0.0,
+            last_x: 0.0,
+            last_time: Instant::now(),
+            momentum: false,
+        }
+    }
+    pub fn offset(&self) -> f64 {
+        self.offset
+    }
+    pub fn set_max_offset(&mut self, max_offset: f64) {
+        self.max_offset = max_offset.max(0.0);
+        self.offset = self.offset.clamp(0.0, self.max_offset);
+    }
+    pub fn at_start(&self) -> bool {
+        self.offset <= 0.0
+    }
+    pub fn at_end(&self) -> bool {
+        self.offset >= self.max_offset
+    }
+    // Starts tracking a finger, stopping any ongoing fling
+    pub fn begin(&mut self, x: f64) {
+        self.last_x = x;
+        self.last_time = Instant::now();
+        self.velocity = 0.0;
+        self.momentum = false;
+    }
+    // Follows the finger, returns true if the offset changed
+    pub fn drag(&mut self, x: f64) -> bool {
+        let now = Instant::now();
+        let dt = (now - self.last_time).as_secs_f64();
+        let dx = self.last_x - x;
+        if dt > 0.0 {
+            // smooth the velocity so a single jittery sample does not dominate
+            self.velocity = self.velocity * 0.5 + dx / dt * 0.5;
+        }
+        self.last_x = x;
+        self.last_time = now;
+        self.scroll_by(dx)
+    }
+    pub fn release(&mut self) {
+        // a finger that rested before lifting should not fling
+        if (Instant::now() - self.last_time) > FRAME_TIME * 4 {
+            self.velocity = 0.0;
+        }
+        self.momentum = self.velocity.abs() > MIN_VELOCITY;
+        self.last_time = Instant::now();
+    }
+    fn scroll_by(&mut self, dx: f64) -> bool {
+        let offset = (self.offset + dx).clamp(0.0, self.max_offset);
+        let changed = offset != self.offset;
+        self.offset = offset;
+        changed
+    }
+    // Advances a fling, returns true if the offset changed
+    pub fn tick(&mut self) -> bool {
+        if !self.momentum {
+            return false;
+        }
+        let now = Instant::now();
+        if now < self.last_time + FRAME_TIME {
+            return false;
+        }
+        let dt = (now - self.last_time).as_secs_f64();
+        self.last_time = now;
+        let changed = self.scroll_by(self.velocity * dt);
+        self.velocity *= (1.0 - FRICTION).powf(dt);
+        if !changed || self.velocity.abs() < MIN_VELOCITY {
+            self.momentum = false;
+            self.velocity = 0.0;
+        }
+        changed
+    }
+    pub fn next_update(&self) -> Option<Instant> {
+        self.momentum.then(|| self.last_time + FRAME_TIME)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Pretends the last sample or frame was the given time ago
+    fn elapse(scroll: &mut Scroll, millis: u64) {
+        scroll.last_time = Instant::now() - Duration::from_millis(millis);
+    }
+
+    fn fling(velocity: f64, max_offset: f64) -> Scroll {
+        let mut scroll = Scroll::new();
+        scroll.set_max_offset(max_offset);
+        scroll.velocity = velocity;
+        scroll.release();
+        scroll
+    }
+
+    #[test]
+    fn drags_are_clamped() {
+        let mut scroll = Scroll::new();
+        scroll.set_max_offset(100.0);
+        scroll.begin(200.0);
+        assert!(scroll.drag(150.0));
+        assert_eq!(scroll.offset(), 50.0);
+        scroll.drag(-100.0);
+        assert_eq!(scroll.offset(), 100.0);
+        assert!(scroll.at_end());
+        // further drags past the end change nothing
+        assert!(!scroll.drag(-200.0));
+        scroll.drag(500.0);
+        assert_eq!(scroll.offset(), 0.0);
+        assert!(scroll.at_start());
+    }
+
+    #[test]
+    fn shrinking_clamps_the_offset() {
+        let mut scroll = Scroll::new();
+        scroll.set_max_offset(100.0);
+        scroll.begin(100.0);
+        scroll.drag(20.0);
+        scroll.set_max_offset(30.0);
+        assert_eq!(scroll.offset(), 30.0);
+        scroll.set_max_offset(-10.0);
+        assert_eq!(scroll.offset(), 0.0);
+    }
+
+    #[test]
+    fn momentum_decays() {
+        let mut scroll = fling(1000.0, 10000.0);
+        assert!(scroll.next_update().is_some());
+        // frames are not advanced before they are due
+        assert!(!scroll.tick());
+        elapse(&mut scroll, 100);
+        assert!(scroll.tick());
+        assert!((scroll.offset() - 100.0).abs() < 5.0);
+        let mut velocity = scroll.velocity;
+        assert!(velocity < 1000.0);
+        for _ in 0..1000 {
+            elapse(&mut scroll, 100);
+            if !scroll.tick() {
+                break;
+            }
+            assert!(scroll.velocity < velocity);
+            velocity = scroll.velocity;
+        }
+        assert!(scroll.next_update().is_none());
+        assert_eq!(scroll.velocity, 0.0);
+    }
+
+    #[test]
+    fn momentum_stops_at_the_end() {
+        let mut scroll = fling(1000.0, 50.0);
+        elapse(&mut scroll, 100);
+        assert!(scroll.tick());
+        assert_eq!(scroll.offset(), 50.0);
+        elapse(&mut scroll, 100);
+        assert!(!scroll.tick());
+        assert!(scroll.next_update().is_none());
+    }
+
+    #[test]
+    fn slow_or_resting_fingers_do_not_fling() {
+        assert!(fling(MIN_VELOCITY / 2.0, 1000.0).next_update().is_none());
+        let mut scroll = Scroll::new();
+        scroll.set_max_offset(1000.0);
+        scroll.velocity = 1000.0;
+        elapse(&mut scroll, 200);
+        scroll.release();
+        assert!(scroll.next_update().is_none());
+    }
+}
@@ -0,0 +1,279 @@
+use std::{
+    fs,
+    io::{self, BufRead, BufReader, Read, Write},
+    net::Shutdown,
+    os::{
+        fd::{AsRawFd, RawFd},
+        linux::net::SocketAddrExt,
+        unix::net::{SocketAddr, UnixListener, UnixStream},
+    },
+    path::PathBuf,
+    process,
+    thread,
+    time::Duration,
+};
+use anyhow::{Result, anyhow};
+use nix::{
+    errno::Errno,
+    fcntl::OFlag,
+    poll::{poll, PollFd, PollFlags},
+    sys::{
+        signal::{signal, SigHandler, Signal},
+        socket::{getsockopt, sockopt::PeerCredentials},
+    },
+    unistd::{close, fork, pipe2, setgid, setgroups, setuid, ForkResult, Uid, User},
+};
+use crate::focus::{self, FocusSource};
+
+// how long the daemon waits for the helper to reach the socket
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(2);
+// logind's state of the seat with the built in display
+const SEAT_STATE: &str = "/run/systemd/seats/seat0";
+// longer lines than this are not part of a D-Bus authentication
+const MAX_AUTH_LINE: usize = 16 * 1024;
+
+// Sockets in the user session the helper connects to
+#[derive(Clone, Copy)]
+pub enum Target {
+    Compositor(FocusSource),
+    SessionBus,
+}
+
+impl Target {
+    const ALL: [Target; 4] = [
+        Target::Compositor(FocusSource::Sway),
+        Target::Compositor(FocusSource::I3),
+        Target::Compositor(FocusSource::Hyprland),
+        Target::SessionBus,
+    ];
+    fn name(self) -> &'static str {
+        match self {
+            Target::Compositor(FocusSource::Sway) => "sway",
+            Target::Compositor(FocusSource::I3) => "i3",
+            Target::Compositor(FocusSource::Hyprland) => "hyprland",
+            Target::SessionBus => "bus",
+        }
+    }
+}
+
+// The helper listens on abstract sockets named after the daemon's pid
+fn address(daemon: u32, target: Target) -> io::Result<SocketAddr> {
+    SocketAddr::from_abstract_name(format!("tiny-dfr-{}-{}", daemon, target.name()))
+}
+
+// libdbus connects by itself, so D-Bus clients get an address to reach the
+// session bus of the logged in user through the helper
+#[cfg_attr(not(feature = "dbus"), allow(dead_code))]
+pub fn bus_address() -> String {
+    format!("unix:abstract=tiny-dfr-{}-{}", process::id(), Target::SessionBus.name())
+}
+
+// Connects to a socket in the user session through the helper
+pub fn connect(target: Target) -> Result<UnixStream> {
+    let stream = UnixStream::connect_addr(&address(process::id(), target)?)
+        .map_err(|e| anyhow!("Session helper is not running: {}", e))?;
+    // the helper reports whether it got through before relaying anything
+    stream.set_read_timeout(Some(CONNECT_TIMEOUT))?;
+    let mut status = String::new();
+    BufReader::new(&stream).read_line(&mut status)?;
+    match status.trim_end() {
+        "ok" => {
+            stream.set_read_timeout(None)?;
+            Ok(stream)
+        }
+        "" => Err(anyhow!("Session helper closed the connection")),
+        error => Err(anyhow!("{}", error)),
+    }
+}
+
+// The runtime dirs in /run/user are only accessible to their owners, which
+// the daemon stops being able to reach once it runs as nobody. So before
+// dropping privileges a helper is forked, which connects to sockets of the
+// user active on seat0 on behalf of the daemon and relays the connections. It has to be
+// spawned before anything else is opened, so the helper holds nothing
+// but its sockets.
+pub fn spawn_helper() -> Result<()> {
+    let daemon = process::id();
+    // bound up front, so the daemon can connect as soon as this returns
+    let listeners = Target::ALL
+        .into_iter()
+        .map(|target| Ok((target, UnixListener::bind_addr(&address(daemon, target)?)?)))
+        .collect::<Result<Vec<_>>>()?;
+    // the helper goes away once the daemon's end is closed by its exit. The
+    // parent death signal would not do, it is not sent after dropping privileges.
+    let (alive, daemon_alive) = pipe2(OFlag::O_CLOEXEC)?;
+    match unsafe { fork() }? {
+        ForkResult::Parent { .. } => {
+            let _ = close(alive);
+            Ok(())
+        }
+        ForkResult::Child => {
+            let _ = close(daemon_alive);
+            run_helper(daemon, alive, &listeners);
+            process::exit(0);
+        }
+    }
+}
+
+fn run_helper(daemon: u32, alive: RawFd, listeners: &[(Target, UnixListener)]) {
+    // relays are never waited for
+    let _ = unsafe { signal(Signal::SIGCHLD, SigHandler::SigIgn) };
+    loop {
+        let mut pollfds = listeners
+            .iter()
+            .map(|(_, listener)| PollFd::new(listener.as_raw_fd(), PollFlags::POLLIN))
+            .chain([PollFd::new(alive, PollFlags::POLLIN)])
+            .collect::<Vec<_>>();
+        match poll(&mut pollfds, -1) {
+            Ok(_) | Err(Errno::EINTR) => {}
+            Err(e) => {
+                eprintln!("Session helper failed to wait for connections: {}", e);
+                return;
+            }
+        }
+        let ready = pollfds
+            .iter()
+            .map(|pollfd| pollfd.revents().is_some_and(|r| !r.is_empty()))
+            .collect::<Vec<_>>();
+        if ready[listeners.len()] {
+            return;
+        }
+        for ((target, listener), ready) in listeners.iter().zip(ready) {
+            if !ready {
+                continue;
+            }
+            let Ok((client, _)) = listener.accept() else {
+                continue;
+            };
+            // anyone may connect to an abstract socket, only serve the daemon
+            let from_daemon = getsockopt(client.as_raw_fd(), PeerCredentials)
+                .is_ok_and(|cred| cred.pid() as u32 == daemon);
+            if !from_daemon {
+                continue;
+            }
+            match unsafe { fork() } {
+                Ok(ForkResult::Parent { .. }) => {}
+                Ok(ForkResult::Child) => {
+                    relay(*target, client);
+                    process::exit(0);
+                }
+                Err(e) => eprintln!("Session helper failed to fork: {}", e),
+            }
+        }
+    }
+}
+
+// The user of the session in the foreground on the built in display, the
+// only one whose sockets the helper connects to. This is the file logind
+// keeps for the seat, sd_seat_get_active() reads the same.
+fn active_user() -> Result<Uid> {
+    let state = fs::read_to_string(SEAT_STATE).map_err(|e| anyhow!("Failed to read {}: {}", SEAT_STATE, e))?;
+    let uid = state
+        .lines()
+        .find_map(|line| line.strip_prefix("ACTIVE_UID="))
+        .ok_or(anyhow!("No active session on seat0"))?
+        .parse()?;
+    let uid = Uid::from_raw(uid);
+    // root's own session is not a desktop session
+    if uid.is_root() {
+        return Err(anyhow!("Not connecting to a session of root"));
+    }
+    Ok(uid)
+}
+
+// Switches to the active user, so the helper never reaches anything that
+// user could not reach themselves
+fn become_user(uid: Uid) -> Result<()> {
+    let user = User::from_uid(uid)?.ok_or(anyhow!("Unknown user {}", uid))?;
+    setgroups(&[user.gid])?;
+    setgid(user.gid)?;
+    setuid(user.uid)?;
+    Ok(())
+}
+
+fn open(target: Target) -> Result<UnixStream> {
+    let uid = active_user()?;
+    let path = match target {
+        Target::Compositor(source) => {
+            focus::discover_socket(source, uid.as_raw()).ok_or(anyhow!("No compositor socket found"))?
+        }
+        Target::SessionBus => PathBuf::from(format!("/run/user/{}/bus", uid)),
+    };
+    if !focus::owned_by(&path, uid.as_raw()) {
+        return Err(anyhow!("{} is not in a directory of the active user", path.display()));
+    }
+    become_user(uid)?;
+    UnixStream::connect(&path).map_err(|e| anyhow!("Failed to connect to {}: {}", path.display(), e))
+}
+
+// Reads a line of the D-Bus authentication a byte at a time, so none of
+// the messages following it are taken from the stream
+fn read_auth_line(stream: &mut UnixStream) -> Result<String> {
+    let mut line = Vec::new();
+    let mut byte = [0u8];
+    while !line.ends_with(b"\r\n") {
+        if line.len() > MAX_AUTH_LINE {
+            return Err(anyhow!("D-Bus authentication line too long"));
+        }
+        stream.read_exact(&mut byte)?;
+        line.push(byte[0]);
+    }
+    line.truncate(line.len() - 2);
+    Ok(String::from_utf8(line)?)
+}
+
+// The bus only lets in its owner, whom the daemon cannot authenticate as.
+// So the relay authenticates towards the bus itself and lets the daemon
+// through with whatever it offers, then relays the messages unchanged.
+fn authenticate(client: &mut UnixStream, bus: &mut UnixStream) -> Result<()> {
+    let uid = Uid::current().to_string();
+    let hex_uid = uid.bytes().map(|b| format!("{:02x}", b)).collect::<String>();
+    bus.write_all(format!("\0AUTH EXTERNAL {}\r\n", hex_uid).as_bytes())?;
+    let reply = read_auth_line(bus)?;
+    let guid = reply
+        .strip_prefix("OK ")
+        .ok_or(anyhow!("Session bus rejected the authentication: {}", reply))?
+        .to_string();
+    bus.write_all(b"BEGIN\r\n")?;
+    // the client starts with a nul byte of its own
+    client.read_exact(&mut [0u8])?;
+    loop {
+        let line = read_auth_line(client)?;
+        match line.split(' ').next().unwrap_or("") {
+            "AUTH" => client.write_all(format!("OK {}\r\n", guid).as_bytes())?,
+            "BEGIN" => return Ok(()),
+            // passing file descriptors is not relayed
+            _ => client.write_all(b"ERROR\r\n")?,
+        }
+    }
+}
+
+// Runs in a child of the helper for each connection, until either side closes
+fn relay(target: Target, mut client: UnixStream) {
+    let mut server = match open(target) {
+        Ok(server) => server,
+        // a D-Bus client would not understand the error, it just sees the
+        // connection close
+        Err(_) if matches!(target, Target::SessionBus) => return,
+        Err(e) => {
+            let _ = writeln!(client, "{}", e);
+            return;
+        }
+    };
+    let ready = match target {
+        Target::SessionBus => authenticate(&mut client, &mut server),
+        Target::Compositor(_) => writeln!(client, "ok").map_err(|e| e.into()),
+    };
+    if ready.is_err() {
+        return;
+    }
+    let (Ok(mut client_read), Ok(mut server_write)) = (client.try_clone(), server.try_clone()) else {
+        return;
+    };
+    thread::spawn(move || {
+        let _ = io::copy(&mut client_read, &mut server_write);
+        let _ = server_write.shutdown(Shutdown::Both);
+    });
+    let _ = io::copy(&mut &server, &mut &client);
+    let _ = client.shutdown(Shutdown::Both);
+}
@@ -0,0 +1,227 @@
+use std::{
+    process::{Child, Command, Stdio},
+    thread,
+    time::{Duration, Instant},
+};
+use input_linux::Key;
+use crate::backlight::DisplayBacklight;
+
+const SETTER_POLL: Duration = Duration::from_millis(100);
+
+pub enum SliderTarget {
+    // (decrease, increase) keys emitted once per step
+    Keys(Key, Key),
+    // setter command, "{}" is replaced with the value in percent
+    Command(String),
+    Backlight(DisplayBacklight),
+}
+
+pub struct Slider {
+    value: f64,
+    steps: u32,
+    target: SliderTarget,
+    setter_child: Option<Child>,
+    pending: bool,
+    // how often levels that are not reported by a getter are re-read
+    refresh: Duration,
+    next_refresh: Instant,
+    // touch position and value when the current drag started
+    drag_start: Option<(f64, f64)>,
+}
+
+impl Slider {
+    pub fn new(steps: u32, target: SliderTarget, refresh: f64) -> Slider {
+        let mut slider = Slider {
+            value: 0.0,
+            steps: steps.max(1),
+            target,
+            setter_child: None,
+            pending: false,
+            refresh: Duration::from_secs_f64(refresh.max(0.1)),
+            next_refresh: Instant::now(),
+            drag_start: None,
+        };
+        slider.update();
+        slider
+    }
+    pub fn value(&self) -> f64 {
+        self.value
+    }
+    pub fn keys(&self) -> Option<(Key, Key)> {
+        match self.target {
+            SliderTarget::Keys(decrease, increase) => Some((decrease, increase)),
+            _ => None,
+        }
+    }
+    pub fn start_drag(&mut self, position: f64) {
+        self.drag_start = Some((position.clamp(0.0, 1.0), self.value));
+    }
+    pub fn end_drag(&mut self) {
+        self.drag_start = None;
+    }
+    fn step(&self, value: f64) -> i32 {
+        (value * self.steps as f64).round() as i32
+    }
+    // Reflects an externally read level, ignored while the user is dragging
+    pub fn set_level(&mut self, percent: f64) -> bool {
+        let value = (percent / 100.0).clamp(0.0, 1.0);
+        if self.drag_start.is_some() || value == self.value {
+            return false;
+        }
+        self.value = value;
+        true
+    }
+    // Sets the value from a drag to position, returns the number of steps
+    // the level moved by so key based sliders can emit them.
+    pub fn set_value(&mut self, position: f64) -> i32 {
+        let mut value = position.clamp(0.0, 1.0);
+        if let (SliderTarget::Keys(..), Some((start, start_value))) = (&self.target, self.drag_start) {
+            // keys only nudge the real level, so key based sliders follow
+            // the finger from wherever the level was when the drag started
+            value = (start_value + value - start).clamp(0.0, 1.0);
+        }
+        let steps = self.step(value) - self.step(self.value);
+        match &self.target {
+            SliderTarget::Keys(..) => {
+                // key based sliders can only move in whole steps
+                self.value = (self.step(self.value) + steps) as f64 / self.steps as f64;
+            }
+            SliderTarget::Command(_) => {
+                if value != self.value {
+                    self.value = value;
+                    self.pending = true;
+                    self.run_setter();
+                }
+            }
+            SliderTarget::Backlight(backlight) => {
+                if value != self.value {
+                    self.value = value;
+                    backlight.set_level(value * 100.0);
+                }
+            }
+        }
+        steps
+    }
+    fn run_setter(&mut self) {
+        let SliderTarget::Command(setter) = &self.target else {
+            return;
+        };
+        // only one setter runs at a time, the latest value is applied once it exits
+        if self.setter_child.is_some() || !self.pending {
+            return;
+        }
+        let percent = (self.value * 100.0).round();
+        let command = setter.replace("{}", &percent.to_string());
+        match Command::new("/bin/sh").arg("-c").arg(&command).stdin(Stdio::null()).spawn() {
+            Ok(child) => self.setter_child = Some(child),
+            Err(e) => eprintln!("Failed to run \"{}\": {}", command, e),
+        }
+        self.pending = false;
+    }
+    // Returns true if the level changed
+    pub fn update(&mut self) -> bool {
+        if let Some(child) = &mut self.setter_child {
+            match child.try_wait() {
+                Ok(None) => return false,
+                Ok(Some(_)) => {}
+                Err(e) => eprintln!("Failed to wait for slider setter: {}", e),
+            }
+            self.setter_child = None;
+        }
+        self.run_setter();
+        let now = Instant::now();
+        if let SliderTarget::Backlight(backlight) = &self.target {
+            if now >= self.next_refresh {
+                self.next_refresh = now + self.refresh;
+                match backlight.level() {
+                    Ok(level) => return self.set_level(level),
+                    Err(e) => eprintln!("Failed to read display brightness: {}", e),
+                }
+            }
+        }
+        false
+    }
+    pub fn next_update(&self) -> Option<Instant> {
+        if self.setter_child.is_some() {
+            return Some(Instant::now() + SETTER_POLL);
+        }
+        match self.target {
+            SliderTarget::Backlight(_) => Some(self.next_refresh),
+            _ => None,
+        }
+    }
+}
+
+impl Drop for Slider {
+    fn drop(&mut self) {
+        // let a slow setter finish applying the level, but reap it off the
+        // main loop so a reload does not wait for it
+        if let Some(mut child) = self.setter_child.take() {
+            if let Ok(None) = child.try_wait() {
+                thread::spawn(move || child.wait());
+            }
+        }
+    }
+}
+
+// The numbers in the text, and whether a percent sign follows them
+fn numbers(text: &str) -> Vec<(f64, bool)> {
+    let is_number = |c: char| c.is_ascii_digit() || c == '.';
+    let mut numbers = Vec::new();
+    let mut rest = text;
+    while let Some(start) = rest.find(is_number) {
+        rest = &rest[start..];
+        let end = rest.find(|c: char| !is_number(c)).unwrap_or(rest.len());
+        if let Ok(number) = rest[..end].parse::<f64>() {
+            numbers.push((number, rest[end..].trim_start().starts_with('%')));
+        }
+        rest = &rest[end..];
+    }
+    numbers
+}
+
+// Extracts the level printed by a getter command in percent. The first
+// percentage wins, like the "50%" of brightnessctl -m or pactl. wpctl prints
+// the volume as a fraction instead, other commands just a number.
+pub fn parse_level(text: &str) -> Option<f64> {
+    let numbers = numbers(text);
+    if let Some((level, _)) = numbers.iter().find(|(_, percent)| *percent) {
+        return Some(*level);
+    }
+    let (first, _) = numbers.first()?;
+    match text.trim_start().starts_with("Volume:") {
+        true => Some(first * 100.0),
+        false => Some(*first),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn brightnessctl_levels() {
+        // brightnessctl -d kbd_backlight -m
+        assert_eq!(parse_level("kbd_backlight,leds,128,50%,255\n"), Some(50.0));
+        // the same piped through cut -d, -f4
+        assert_eq!(parse_level("50%\n"), Some(50.0));
+    }
+
+    #[test]
+    fn volume_levels() {
+        // wpctl get-volume @DEFAULT_AUDIO_SINK@
+        assert_eq!(parse_level("Volume: 0.40\n"), Some(40.0));
+        assert_eq!(parse_level("Volume: 1.20 [MUTED]\n"), Some(120.0));
+        // pactl get-sink-volume @DEFAULT_SINK@
+        let pactl = "Volume: front-left: 26214 /  40% / -23.88 dB,   front-right: 26214 /  40% / -23.88 dB\n";
+        assert_eq!(parse_level(pactl), Some(40.0));
+    }
+
+    #[test]
+    fn plain_numbers() {
+        assert_eq!(parse_level("75\n"), Some(75.0));
+        assert_eq!(parse_level("level 12.5"), Some(12.5));
+        assert_eq!(parse_level("off"), None);
+        assert_eq!(parse_level(""), None);
+    }
+}
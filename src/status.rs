@@ -0,0 +1,141 @@
+// State of toggles like microphone mute, shown on a button by swapping its
+// label and color
+use std::{os::fd::RawFd, time::Instant};
+use crate::{command::CommandOutput, led::LedState, Color};
+#[cfg(feature = "dbus")]
+use crate::property::PropertyWatcher;
+
+// Words that mean a toggle is on, like the "yes" of "Mute: yes" or the
+// "[MUTED]" of wpctl, which is why punctuation around the word is ignored
+pub fn parse_state(text: &str) -> bool {
+    let word = text.split_whitespace().last().unwrap_or("");
+    let word = word.trim_matches(|c: char| !c.is_alphanumeric());
+    ["1", "yes", "on", "true", "muted"].iter().any(|on| word.eq_ignore_ascii_case(on))
+}
+
+pub enum StatusSource {
+    Led(LedState),
+    // on when the last word printed means so, see parse_state
+    Command(CommandOutput),
+    #[cfg(feature = "dbus")]
+    Property(PropertyWatcher),
+}
+
+impl StatusSource {
+    // Returns the state if there is news of it
+    fn update(&mut self) -> Option<bool> {
+        match self {
+            StatusSource::Led(led) => {
+                led.update();
+                Some(led.lit())
+            }
+            StatusSource::Command(command) => command.update().map(|output| parse_state(&output.text)),
+            #[cfg(feature = "dbus")]
+            StatusSource::Property(property) => property.update(),
+        }
+    }
+    fn next_update(&self) -> Option<Instant> {
+        match self {
+            StatusSource::Led(led) => led.next_update(),
+            StatusSource::Command(command) => command.next_update(),
+            #[cfg(feature = "dbus")]
+            StatusSource::Property(property) => property.next_update(),
+        }
+    }
+    fn poll_fd(&self) -> Option<RawFd> {
+        match self {
+            StatusSource::Led(_) => None,
+            StatusSource::Command(command) => command.poll_fd(),
+            #[cfg(feature = "dbus")]
+            StatusSource::Property(property) => property.poll_fd(),
+        }
+    }
+}
+
+pub struct Toggle {
+    source: StatusSource,
+    on: bool,
+    // icon names, or text if there is no icon theme
+    pub on_label: String,
+    pub off_label: String,
+    pub on_color: Option<Color>,
+    pub off_color: Option<Color>,
+    pub icon_theme: Option<String>,
+}
+
+impl Toggle {
+    pub fn new(source: StatusSource) -> Toggle {
+        Toggle {
+            source,
+            on: false,
+            on_label: String::new(),
+            off_label: String::new(),
+            on_color: None,
+            off_color: None,
+            icon_theme: None,
+        }
+    }
+    pub fn watch_led(&mut self, watched: bool) {
+        if let StatusSource::Led(led) = &mut self.source {
+            led.watch(watched);
+        }
+    }
+    // Returns true if the toggle was switched since the last update
+    pub fn update(&mut self) -> bool {
+        match self.source.update() {
+            Some(on) if on != self.on => {
+                self.on = on;
+                true
+            }
+            _ => false,
+        }
+    }
+    pub fn label(&self) -> &str {
+        match self.on {
+            true => &self.on_label,
+            false => &self.off_label,
+        }
+    }
+    pub fn color(&self) -> Option<Color> {
+        match self.on {
+            true => self.on_color,
+            false => self.off_color,
+        }
+    }
+    pub fn next_update(&self) -> Option<Instant> {
+        self.source.next_update()
+    }
+    pub fn poll_fd(&self) -> Option<RawFd> {
+        self.source.poll_fd()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pactl_mute() {
+        // pactl get-source-mute @DEFAULT_SOURCE@
+        assert!(parse_state("Mute: yes\n"));
+        assert!(!parse_state("Mute: no\n"));
+    }
+
+    #[test]
+    fn wpctl_mute() {
+        // wpctl get-volume @DEFAULT_AUDIO_SOURCE@
+        assert!(parse_state("Volume: 0.40 [MUTED]\n"));
+        assert!(!parse_state("Volume: 0.40\n"));
+        assert!(!parse_state("Volume: 1.00\n"));
+    }
+
+    #[test]
+    fn plain_words() {
+        assert!(parse_state("1"));
+        assert!(parse_state("On"));
+        assert!(parse_state("true\n"));
+        assert!(!parse_state("0"));
+        assert!(!parse_state("off"));
+        assert!(!parse_state(""));
+    }
+}
@@ -0,0 +1,181 @@
+use std::{
+    collections::VecDeque,
+    fs,
+    path::PathBuf,
+    time::{Duration, Instant},
+};
+use anyhow::{Result, anyhow};
+
+const HISTORY_LEN: usize = 32;
+// Temperatures are graphed on a fixed 0..TEMP_SCALE °C range
+const TEMP_SCALE: f64 = 100.0;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum SysLoadKind {
+    Cpu,
+    Memory,
+    Temperature,
+}
+
+pub struct SysLoad {
+    kind: SysLoadKind,
+    sensor: Option<PathBuf>,
+    interval: Duration,
+    next_update: Instant,
+    last_cpu: Option<(u64, u64)>,
+    value: Option<f64>,
+    history: VecDeque<f64>,
+    pub graph: bool,
+}
+
+fn read_cpu_times() -> Result<(u64, u64)> {
+    let stat = fs::read_to_string("/proc/stat")?;
+    let line = stat.lines().next().ok_or(anyhow!("/proc/stat is empty"))?;
+    let fields = line
+        .split_whitespace()
+        .skip(1)
+        .take(8)
+        .map(|f| f.parse::<u64>())
+        .collect::<Result<Vec<_>, _>>()?;
+    if fields.len() < 5 {
+        return Err(anyhow!("Unexpected /proc/stat format"));
+    }
+    // idle + iowait
+    let idle = fields[3] + fields[4];
+    Ok((idle, fields.iter().sum()))
+}
+
+fn read_memory_usage() -> Result<f64> {
+    let meminfo = fs::read_to_string("/proc/meminfo")?;
+    let mut total = None;
+    let mut available = None;
+    for line in meminfo.lines() {
+        let mut parts = line.split_whitespace();
+        match (parts.next(), parts.next()) {
+            (Some("MemTotal:"), Some(v)) => total = Some(v.parse::<f64>()?),
+            (Some("MemAvailable:"), Some(v)) => available = Some(v.parse::<f64>()?),
+            _ => {}
+        }
+    }
+    match (total, available) {
+        (Some(total), Some(available)) if total > 0.0 => Ok((total - available) / total * 100.0),
+        _ => Err(anyhow!("Unexpected /proc/meminfo format")),
+    }
+}
+
+fn find_temp_sensor(sensor: &str) -> Result<PathBuf> {
+    // An empty sensor name picks the first temperature input found,
+    // otherwise it is matched against the hwmon device name and the
+    // individual tempN_label attributes.
+    let mut hwmons = fs::read_dir("/sys/class/hwmon/")?
+        .filter_map(|e| e.ok())
+        .map(|e| e.path())
+        .collect::<Vec<_>>();
+    hwmons.sort();
+    for hwmon in hwmons {
+        let name = fs::read_to_string(hwmon.join("name")).unwrap_or_default();
+        let mut inputs = fs::read_dir(&hwmon)?
+            .filter_map(|e| e.ok())
+            .map(|e| e.file_name().to_string_lossy().into_owned())
+            .filter(|f| f.starts_with("temp") && f.ends_with("_input"))
+            .collect::<Vec<_>>();
+        inputs.sort();
+        for input in inputs {
+            let label_attr = input.replace("_input", "_label");
+            let label = fs::read_to_string(hwmon.join(label_attr)).unwrap_or_default();
+            if sensor.is_empty() || name.trim() == sensor || label.trim() == sensor {
+                return Ok(hwmon.join(input));
+            }
+        }
+    }
+    Err(anyhow!("No temperature sensor matching \"{}\" found", sensor))
+}
+
+impl SysLoad {
+    pub fn new(kind: SysLoadKind, sensor: &str, interval: f64, graph: bool) -> Result<SysLoad> {
+        let sensor = match kind {
+            SysLoadKind::Temperature => Some(find_temp_sensor(sensor)?),
+            _ => None,
+        };
+        let mut widget = SysLoad {
+            kind,
+            sensor,
+            interval: Duration::from_secs_f64(interval.max(0.1)),
+            next_update: Instant::now(),
+            last_cpu: None,
+            value: None,
+            history: VecDeque::with_capacity(HISTORY_LEN),
+            graph,
+        };
+        widget.update();
+        Ok(widget)
+    }
+    fn sample(&mut self) -> Result<Option<f64>> {
+        match self.kind {
+            SysLoadKind::Cpu => {
+                let (idle, total) = read_cpu_times()?;
+                let usage = self.last_cpu.and_then(|(last_idle, last_total)| {
+                    let total_delta = total.saturating_sub(last_total);
+                    if total_delta == 0 {
+                        return None;
+                    }
+                    let idle_delta = idle.saturating_sub(last_idle);
+                    Some((total_delta - idle_delta.min(total_delta)) as f64 / total_delta as f64 * 100.0)
+                });
+                self.last_cpu = Some((idle, total));
+                Ok(usage)
+            }
+            SysLoadKind::Memory => Ok(Some(read_memory_usage()?)),
+            SysLoadKind::Temperature => {
+                let path = self.sensor.as_ref().unwrap();
+                let millidegrees = fs::read_to_string(path)?.trim().parse::<f64>()?;
+                Ok(Some(millidegrees / 1000.0))
+            }
+        }
+    }
+    pub fn next_update(&self) -> Instant {
+        self.next_update
+    }
+    // Takes a new sample if the refresh interval has elapsed, returns true
+    // if the displayed value changed.
+    pub fn update(&mut self) -> bool {
+        let now = Instant::now();
+        if now < self.next_update {
+            return false;
+        }
+        self.next_update = now + self.interval;
+        let value = match self.sample() {
+            Ok(Some(value)) => value,
+            Ok(None) => return false,
+            Err(e) => {
+                eprintln!("Failed to read system load: {}", e);
+                return false;
+            }
+        };
+        if self.history.len() == HISTORY_LEN {
+            self.history.pop_front();
+        }
+        let scale = if self.kind == SysLoadKind::Temperature { TEMP_SCALE } else { 100.0 };
+        self.history.push_back((value / scale).clamp(0.0, 1.0));
+        self.value = Some(value);
+        true
+    }
+    pub fn label(&self) -> String {
+        let value = match self.value {
+            Some(value) => value.round(),
+            None => return "--".to_string(),
+        };
+        match self.kind {
+            SysLoadKind::Cpu => format!("CPU {}%", value),
+            SysLoadKind::Memory => format!("MEM {}%", value),
+            SysLoadKind::Temperature => format!("{}°C", value),
+        }
+    }
+    // Samples normalized to 0.0..1.0, oldest first
+    pub fn history(&self) -> impl Iterator<Item = &f64> {
+        self.history.iter()
+    }
+    pub fn history_capacity(&self) -> usize {
+        HISTORY_LEN
+    }
+}
@@ -0,0 +1,41 @@
+// A private dbus-daemon for the tests, so they need neither the system bus
+// nor a desktop session. Tests using it are skipped where it is missing.
+use std::{
+    io::{BufRead, BufReader},
+    process::{Child, Command, Stdio},
+};
+use dbus::channel::Channel;
+
+pub struct TestBus {
+    daemon: Child,
+    pub address: String,
+}
+
+impl TestBus {
+    pub fn start() -> Option<TestBus> {
+        let mut daemon = Command::new("dbus-daemon")
+            .args(["--session", "--nofork", "--print-address"])
+            .stdout(Stdio::piped())
+            .spawn()
+            .map_err(|e| eprintln!("No dbus-daemon, skipping: {}", e))
+            .ok()?;
+        let mut address = String::new();
+        BufReader::new(daemon.stdout.as_mut()?).read_line(&mut address).ok()?;
+        Some(TestBus {
+            daemon,
+            address: address.trim().to_string(),
+        })
+    }
+    pub fn channel(&self) -> Channel {
+        let mut channel = Channel::open_private(&self.address).unwrap();
+        channel.register().unwrap();
+        channel
+    }
+}
+
+impl Drop for TestBus {
+    fn drop(&mut self) {
+        let _ = self.daemon.kill();
+        let _ = self.daemon.wait();
+    }
+}
@@ -0,0 +1,52 @@
+use std::time::{Duration, Instant};
+use cairo::{Context, Format, ImageSurface};
+use crate::gesture::Swipe;
+
+const DURATION: Duration = Duration::from_millis(220);
+const FRAME_TIME: Duration = Duration::from_millis(16);
+
+// Slides the previous layer out while the new one slides in
+pub struct Transition {
+    old: ImageSurface,
+    direction: f64,
+    start: Instant,
+}
+
+impl Transition {
+    pub fn new(current: &ImageSurface, swipe: Swipe) -> Transition {
+        let old = ImageSurface::create(Format::ARgb32, current.width(), current.height()).unwrap();
+        let c = Context::new(&old).unwrap();
+        c.set_source_surface(current, 0.0, 0.0).unwrap();
+        c.paint().unwrap();
+        drop(c);
+        Transition {
+            old,
+            direction: if swipe == Swipe::Next { 1.0 } else { -1.0 },
+            start: Instant::now(),
+        }
+    }
+    fn progress(&self) -> f64 {
+        let linear = (Instant::now() - self.start).as_secs_f64() / DURATION.as_secs_f64();
+        // ease out
+        1.0 - (1.0 - linear.min(1.0)).powi(3)
+    }
+    pub fn done(&self) -> bool {
+        Instant::now() - self.start >= DURATION
+    }
+    pub fn next_update(&self) -> Instant {
+        Instant::now() + FRAME_TIME
+    }
+    // Renders the current frame of the animation into target. The surfaces
+    // are rotated, so the length of the bar runs along their y axis.
+    pub fn compose(&self, new: &ImageSurface, target: &ImageSurface) {
+        let length = target.height() as f64;
+        let progress = self.progress();
+        let c = Context::new(target).unwrap();
+        c.set_source_rgb(0.0, 0.0, 0.0);
+        c.paint().unwrap();
+        c.set_source_surface(&self.old, 0.0, -self.direction * progress * length).unwrap();
+        c.paint().unwrap();
+        c.set_source_surface(new, 0.0, self.direction * (1.0 - progress) * length).unwrap();
+        c.paint().unwrap();
+    }
+}